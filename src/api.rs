@@ -0,0 +1,1149 @@
+use std::{ collections::{HashMap, VecDeque}, sync::Arc };
+use tokio::{task::JoinHandle};
+use visdom::Vis;
+use futures::{Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use log::{error, warn, info};
+use crate::session::{self};
+use crate::disk::DiskGuard;
+use crate::post::{self, PostDetail, PostMeta};
+use crate::naming::{self, NameFields, Naming};
+use crate::media::{self, MediaFile, SNIFF_LEN};
+use crate::hls;
+use crate::store::{Placed, Store};
+use crate::check::{self, Quarantine};
+use crate::filter::{Filter, Skip, Window};
+use crate::plan::{self, Plan, PagePlan, PostPlan};
+use crate::event::{self, Event, Events, Meter};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use walkdir::WalkDir;
+
+// 创建目录
+pub async fn create_dir(path: &PathBuf) -> bool {
+    if path.exists() {
+        return true;
+    }
+    match tokio::fs::create_dir_all(path).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("create dir: {} error: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+// 所有标签共用的下载设置, 并发数限制同时下载的文件数, 每个帖子目录内也按并发数同时下载
+#[derive(Clone)]
+pub struct Downloader
+{
+    session: session::Session,
+    // 内容寻址存储, None 时不去重
+    store: Option<Store>,
+    // 无效文件隔离目录
+    quarantine: Quarantine,
+    limit: Arc<Semaphore>,
+    concurrency: usize,
+    // 下载出错后的重试次数
+    retries: usize,
+    events: Events,
+}
+
+// 下载文件, 返回下载成功的文件
+// 同时下载 concurrency 个文件, 所有帖子共用的信号量限制总并发数
+// done 为上次运行中已下载的文件, 序号和 URL 相同时不再下载
+pub async fn download_files(dl: Downloader, tp: &str, folder: PathBuf, vec: VecDeque<String>, done: Vec<MediaFile>) -> Vec<MediaFile> {
+    if !create_dir(&folder).await {
+        error!("create dir: {} error!", &folder.display().to_string());
+        return Vec::new();
+    }
+    let info: String = vec.iter().map(|url| format!("{}\n", url)).collect();
+    if let Err(e) = std::fs::write(folder.join("info.txt"), info) {
+        warn!("write info.txt in {} error: {}", folder.display(), e);
+    }
+    let sub = folder.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let width = media::index_width(vec.len());
+
+    warn!("start download {} {} in dir: {}", vec.len(), tp, &folder.display());
+    let (dl, folder, sub, done) = (&dl, &folder, &sub, &done);
+    let mut files: Vec<MediaFile> = futures::stream::iter(vec.into_iter().enumerate())
+        .map(|(n, url)| async move {
+            let index = n + 1;
+            match done.iter().find(|f| f.index == index && f.url == url) {
+                Some(f) => Some(f.clone()),
+                None => download_one(dl, folder, sub, index, width, url).await,
+            }
+        })
+        .buffer_unordered(dl.concurrency)
+        .filter_map(|f| async move { f })
+        .collect()
+        .await;
+    files.sort_by_key(|f| f.index);
+    files
+}
+
+// 下载, 校验并去重一个文件, 失败时返回 None
+async fn download_one(dl: &Downloader, folder: &Path, sub: &str, i: usize, width: usize, url: String) -> Option<MediaFile> {
+    let session = &dl.session;
+    let permit = dl.limit.acquire().await;
+    let start = std::time::Instant::now();
+    dl.events.emit(Event::FileStarted { url: url.clone(), dir: folder.display().to_string() });
+    // 只重试请求和写入出错, 下载后校验无效的不重试
+    let mut attempt = 1;
+    let res = loop {
+        let res = if media::is_hls(&url) {
+            hls::download(session, &dl.events, &url, folder, i, width).await.map(|(name, size)| (name, size, None))
+        } else {
+            download_file(session, &dl.events, &url, folder, i, width).await
+        };
+        match res {
+            Err(e) if attempt <= dl.retries => {
+                warn!("download {} error: {}, retry {}/{}", &url, e, attempt, dl.retries);
+                dl.events.emit(Event::FileRetry { url: url.clone(), dir: folder.display().to_string(), attempt, error: format!("{:#}", e) });
+                tokio::time::sleep(tokio::time::Duration::from_secs(2 * attempt as u64)).await;
+                attempt += 1;
+            }
+            res => break res,
+        }
+    };
+    drop(permit);
+    let res = match res {
+        Ok((name, size, expected)) => validate(&dl.quarantine, folder, &url, name, expected).await.map(|name| (name, size)),
+        Err(e) => Err(e)
+    };
+    match res {
+        Ok((name, size)) => {
+            if let Some(store) = &dl.store {
+                dedupe(store, folder.join(&name)).await;
+            }
+            dl.events.emit(Event::FileCompleted {
+                url: url.clone(),
+                dir: folder.display().to_string(),
+                file: folder.join(&name).display().to_string(),
+                bytes: size,
+                duration_ms: event::elapsed_ms(start),
+            });
+            Some(MediaFile {
+                index: i,
+                url,
+                file: format!("{}/{}", sub, name),
+                size,
+            })
+        },
+        Err(e) => {
+            error!("download {} error: {}", &url, e);
+            dl.events.emit(Event::FileFailed { url, dir: folder.display().to_string(), error: format!("{:#}", e), duration_ms: event::elapsed_ms(start) });
+            None
+        }
+    }
+}
+
+// 检查下载的文件, 无效时移入 failed/ 并返回错误
+async fn validate(quarantine: &Quarantine, folder: &Path, url: &str, name: String, expected: Option<u64>) -> anyhow::Result<String> {
+    let path = folder.join(&name);
+    let (quarantine, url) = (quarantine.clone(), url.to_string());
+    tokio::task::spawn_blocking(move || {
+        match check::check_file(&path, expected) {
+            Ok(_) => Ok(name),
+            Err(reason) => {
+                if let Err(e) = quarantine.put(&path, Some(&url), &reason) {
+                    warn!("quarantine {} error: {}", path.display(), e);
+                }
+                Err(anyhow::anyhow!("invalid media: {}", reason))
+            }
+        }
+    }).await?
+}
+
+// 下载单个文件, 扩展名依次取自 URL, Content-Type, 文件头
+// 返回文件名, 大小和响应的 Content-Length
+async fn download_file(session: &session::Session, events: &Events, url: &str, folder: &Path, index: usize, width: usize) -> anyhow::Result<(String, u64, Option<u64>)> {
+    let part = folder.join(format!(".{:0width$}.part", index, width = width));
+    let result = async {
+        let mut res = session.http_get(url).await?.error_for_status()?;
+        let expected = res.content_length();
+        let content_type = res.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        let mut file = tokio::fs::File::create(&part).await?;
+        let mut head: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+        let mut size = 0u64;
+        let mut meter = Meter::new(events, url, folder, expected);
+        while let Some(chunk) = session.chunk(&mut res).await? {
+            if head.len() < SNIFF_LEN {
+                head.extend_from_slice(&chunk[..chunk.len().min(SNIFF_LEN - head.len())]);
+            }
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+            meter.add(chunk.len() as u64);
+        }
+        file.flush().await?;
+        let ext = media::ext_from_url(url)
+            .or_else(|| content_type.as_deref().and_then(media::ext_from_content_type))
+            .or_else(|| media::sniff_ext(&head))
+            .unwrap_or_else(|| "bin".to_string());
+        let name = media::file_name(index, width, url, &ext);
+        tokio::fs::rename(&part, folder.join(&name)).await?;
+        Ok((name, size, expected))
+    }.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
+// 将下载的文件放入内容寻址存储, 与其他帖子中的相同文件共用数据
+async fn dedupe(store: &Store, path: PathBuf) {
+    let store = store.clone();
+    let res = tokio::task::spawn_blocking(move || {
+        let placed = store.place(&path);
+        (path, placed)
+    }).await;
+    match res {
+        Ok((path, Ok(Placed::Linked(size)))) => info!("{} is a duplicate, linked, saved {} bytes", path.display(), size),
+        Ok((_, Ok(_))) => {}
+        Ok((path, Err(e))) => warn!("dedupe {} error: {}", path.display(), e),
+        Err(e) => warn!("dedupe task error: {}", e),
+    }
+}
+
+pub async fn write_meta(folder: &PathBuf, meta: &PostMeta) -> bool {
+    if !create_dir(folder).await {
+        return false;
+    }
+    let data = match serde_json::to_vec_pretty(meta) {
+        Ok(d) => d,
+        Err(e) => {
+            error!("serialize meta of {} error: {}", &meta.url, e);
+            return false;
+        }
+    };
+    match tokio::fs::write(folder.join("meta.json"), data).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("write meta.json in {} error: {}", folder.display(), e);
+            false
+        }
+    }
+}
+
+// 目录中 meta.json 记录的是否为同一个帖子, 没有 meta.json 的旧目录视为同一个
+fn same_post(dir: &Path, url: &str) -> bool {
+    let meta: Option<serde_json::Value> = std::fs::read(dir.join("meta.json")).ok()
+        .and_then(|d| serde_json::from_slice(&d).ok());
+    match meta.as_ref().and_then(|m| m.get("url")).and_then(|u| u.as_str()) {
+        Some(u) => u == url,
+        None => true
+    }
+}
+
+// 帖子目录 meta.json 中记录的文件, 只保留仍然存在的
+fn downloaded_files(dir: &Path) -> Vec<MediaFile> {
+    #[derive(serde::Deserialize)]
+    struct Files { #[serde(default)] files: Vec<MediaFile> }
+    std::fs::read(dir.join("meta.json")).ok()
+        .and_then(|d| serde_json::from_slice::<Files>(&d).ok())
+        .map(|m| m.files.into_iter().filter(|f| dir.join(&f.file).is_file()).collect())
+        .unwrap_or_default()
+}
+
+// 帖子是否下载完成, 旧版本的 meta.json 没有 complete, 按已记录的文件数判断
+fn post_complete(meta: &serde_json::Value) -> bool {
+    if let Some(complete) = meta.get("complete").and_then(|c| c.as_bool()) {
+        return complete;
+    }
+    let count = |key: &str| meta.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let files = meta.get("files").and_then(|f| f.as_array()).map(|f| f.len()).unwrap_or(0);
+    files as u64 >= count("image_count") + count("video_count")
+}
+
+// 输出目录中已下载帖子的 URL 与目录, 分为已完成和未完成的
+fn downloaded_posts(root: &Path) -> (HashMap<String, PathBuf>, HashMap<String, PathBuf>) {
+    let mut posts = HashMap::new();
+    let mut partial = HashMap::new();
+    // 命名模板的目录层数不固定, 不限制深度, 跳过帖子中的媒体目录
+    let walker = WalkDir::new(root).into_iter()
+        .filter_entry(|e| {
+            if e.depth() == 1 && check::is_reserved(e.file_name()) {
+                return false;
+            }
+            !(e.file_type().is_dir() && (e.file_name() == "imgs" || e.file_name() == "videos"))
+        });
+    for entry in walker.filter_map(|e| e.ok()) {
+        if entry.file_name() != "meta.json" {
+            continue;
+        }
+        let meta: Option<serde_json::Value> = std::fs::read(entry.path()).ok()
+            .and_then(|d| serde_json::from_slice(&d).ok());
+        let meta = match meta {
+            Some(meta) => meta,
+            None => continue,
+        };
+        if let Some(url) = meta.get("url").and_then(|u| u.as_str()) {
+            let dir = entry.path().parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            if post_complete(&meta) {
+                posts.insert(url.to_string(), dir);
+            } else {
+                partial.insert(url.to_string(), dir);
+            }
+        }
+    }
+    (posts, partial)
+}
+
+// 每个标签的爬取结果
+#[derive(Debug, Default, serde::Serialize)]
+pub struct TagSummary
+{
+    pub tag: String,
+    pub pages: i32,
+    // 列表页中找到的帖子
+    pub posts: usize,
+    pub downloaded: usize,
+    // 已下载过或在其他标签中出现过
+    pub skipped: usize,
+    pub failed: usize,
+    pub files: usize,
+    // 下载失败的文件
+    pub missing: usize,
+    pub bytes: u64,
+    // 被筛选规则跳过, 记录在 skipped.jsonl
+    pub filtered: usize,
+}
+
+const SITE: &str = "https://www.cosjun.cn";
+
+// 帖子列表来源: 标签归档或 WordPress 站内搜索
+#[derive(Debug, Clone)]
+pub enum Listing {
+    Tag(String),
+    Search(String),
+}
+
+impl Listing {
+    // 日志, 汇总和 {tag} 目录中使用的名称
+    pub fn name(&self) -> String {
+        match self {
+            Listing::Tag(t) => t.clone(),
+            Listing::Search(q) => format!("search-{}", q),
+        }
+    }
+
+    fn first_url(&self) -> String {
+        match self {
+            Listing::Tag(t) => format!("{}/{}?ref=cosjun", SITE, t),
+            Listing::Search(_) => self.page_url(1),
+        }
+    }
+
+    fn page_url(&self, page: i32) -> String {
+        match self {
+            Listing::Tag(t) => format!("{}/{}/page/{}?ref=cosjun", SITE, t, page),
+            Listing::Search(q) => {
+                let q = url_escape::encode_component(q);
+                if page <= 1 {
+                    format!("{}/?s={}", SITE, q)
+                } else {
+                    format!("{}/page/{}/?s={}", SITE, page, q)
+                }
+            }
+        }
+    }
+}
+
+// 被筛选规则跳过的帖子记录
+const SKIPPED_FILE: &str = "skipped.jsonl";
+
+// 直接下载帖子时的日志标签
+const GET_LABEL: &str = "get";
+// 帖子没有分类时代替 {tag} 的目录名
+const GET_TAG: &str = "posts";
+
+// 列表页中的帖子, 每个 .entry-wrapper 一项
+fn list_items(html: &visdom::types::Elements) -> Vec<CosItem> {
+    let mut items = Vec::new();
+    html.find(".entry-wrapper").into_iter().for_each(|entry| {
+        let entry = Vis::dom(&entry);
+        let date = entry.find("time[datetime]").attr("datetime").map(|d| d.to_string())
+            .or_else(|| Some(entry.find(".meta-date").text()))
+            .and_then(|d| crate::filter::parse_date(&d));
+        entry.find(".entry-title").find("a").into_iter().for_each(|item| {
+            // 搜索结果的链接可能没有 title 属性
+            let title = item.get_attribute("title")
+                .map(|t| t.to_string())
+                .or_else(|| Some(item.text().trim().to_string()).filter(|t| !t.is_empty()));
+            let url = item.get_attribute("href");
+            if let Some(title) = title {
+                if let Some(url) = url {
+                    // 添加到队列
+                    items.push(CosItem { title, url: url.to_string(), date: date.clone() });
+                }
+            }
+        });
+    });
+    items
+}
+
+// 生产器 -> 获取总体页数 -> 获取当前处理页数内所有项目并加入链表
+// 消费器 -> 从链表获取头部连接 -> 初始化本地文件夹 -> 请求并下载图片和视频
+
+#[derive(Debug, Clone)]
+pub struct CosItem
+{
+    // 标题(对应本地文件夹名称)
+    pub title: String,
+    // 项目 URL
+    pub url: String,
+    // 列表页中的发布日期
+    pub date: Option<String>
+}
+
+impl CosItem {
+    pub fn new(title: String, url: String) -> Self {
+        Self { title, url, date: None }
+    }
+}
+
+// 帖子列表流中的一项
+#[derive(Debug)]
+pub enum Listed {
+    // 已获取的列表页和页面中的帖子数
+    Page { page: i32, url: String, found: usize },
+    // 通过标题和日期筛选的帖子
+    Post(CosItem),
+    // 被筛选规则跳过的帖子
+    Filtered(CosItem, Skip),
+    // 无法获取或解析列表页, 继续下一页
+    PageFailed { page: i32, url: String, error: String },
+    // 无法获取总页数, 列表结束
+    Failed(String),
+}
+
+// 获取总页数, 没有分页时只有一页
+async fn total_pages(session: &session::Session, listing: &Listing) -> Option<i32> {
+    let name = listing.name();
+    let res = match session.http_get(&listing.first_url()).await {
+        Ok(res) => res,
+        Err(e) => {
+            error!("<{}> ==> init_total_page get http request error:{}", name, e);
+            return None;
+        }
+    };
+    let html = match session.text(res).await {
+        Ok(html) => html,
+        Err(e) => {
+            error!("<{}> ==> init_total_page parse response text error: {}", name, e);
+            return None;
+        }
+    };
+    let html = Vis::load(html).ok()?;
+    let pagination = html.find(".numeric-pagination");
+    if pagination.is_empty() {
+        return Some(1);
+    }
+    let node1 = pagination
+                                .find(".page-numbers")
+                                .find(":nth-last-child(2)");
+    match node1.text().parse::<i32>() {
+        Ok(i) => Some(i),
+        Err(e) => {
+            error!("<{}> ==> init_total_page parse number error: {}", name, e);
+            None
+        }
+    }
+}
+
+// 列表流的翻页状态
+struct ListState
+{
+    session: session::Session,
+    listing: Listing,
+    filter: Filter,
+    // 下一个要获取的页码
+    page: i32,
+    total: Option<i32>,
+    queue: VecDeque<Listed>,
+    done: bool,
+}
+
+impl ListState {
+    // 获取下一页, 结果放入队列
+    fn page_failed(&mut self, page: i32, url: String, error: String) {
+        error!("<{}> => item_produce {}", self.listing.name(), &error);
+        self.queue.push_back(Listed::PageFailed { page, url, error });
+    }
+
+    async fn next_page(&mut self) {
+        let tag = self.listing.name();
+        let total = match self.total {
+            Some(total) => total,
+            None => match total_pages(&self.session, &self.listing).await {
+                Some(total) => {
+                    info!("<{}> ==> total page == {}, crawl page {} to {}, date {}", tag, total, self.filter.from_page, self.filter.to_page, self.filter.describe());
+                    self.total = Some(total);
+                    total
+                }
+                None => {
+                    self.queue.push_back(Listed::Failed(format!("get total page of {} error", tag)));
+                    self.done = true;
+                    return;
+                }
+            }
+        };
+        if self.page > total || (self.filter.to_page != -1 && self.page > self.filter.to_page) {
+            self.done = true;
+            return;
+        }
+        if self.page > self.filter.from_page.max(1) {
+            tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+        }
+        let get_url : String = self.listing.page_url(self.page);
+        info!("<{}> ==> current page: {} ==> {}", tag, self.page, &get_url);
+        let page = self.page;
+        self.page += 1;
+        let html = match self.session.http_get(&get_url).await {
+            Ok(res) => self.session.text(res).await,
+            Err(e) => return self.page_failed(page, get_url, format!("get http request error: {}", e)),
+        };
+        let html = match html {
+            Ok(html) => html,
+            Err(e) => return self.page_failed(page, get_url, format!("get response text error: {}", e)),
+        };
+        let items = match Vis::load(html) {
+            Ok(html) => list_items(&html),
+            Err(e) => return self.page_failed(page, get_url, format!("parse html error: {}", e)),
+        };
+        self.queue.push_back(Listed::Page { page, url: get_url, found: items.len() });
+        if queue_items(&self.listing, &self.filter, items, &mut self.queue) {
+            self.done = true;
+            info!("<{}> ==> posts older than {} reached, stop paging", tag, self.filter.describe());
+        }
+    }
+}
+
+// 按标题和日期筛选列表页中的帖子并加入队列, 返回是否停止翻页
+// 标签归档按时间倒序, 出现早于窗口的帖子后不再翻页, 搜索结果不按时间排序, 翻到最后一页
+fn queue_items(listing: &Listing, filter: &Filter, items: Vec<CosItem>, queue: &mut VecDeque<Listed>) -> bool {
+    let newest_first = matches!(listing, Listing::Tag(_));
+    let mut stop = false;
+    for item in items {
+        if newest_first && filter.window(item.date.as_deref()) == Window::Older {
+            stop = true;
+        }
+        let skip = filter.check_title(&item.title)
+            .or_else(|| filter.check_date(item.date.as_deref()));
+        queue.push_back(match skip {
+            Some(skip) => Listed::Filtered(item, skip),
+            None => Listed::Post(item),
+        });
+    }
+    stop
+}
+
+// 按页获取列表中的帖子, 不下载
+pub fn list(session: session::Session, listing: Listing, filter: Filter) -> impl Stream<Item = Listed> {
+    let state = ListState {
+        session,
+        listing,
+        page: filter.from_page.max(1),
+        filter,
+        total: None,
+        queue: VecDeque::new(),
+        done: false,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(listed) = state.queue.pop_front() {
+                return Some((listed, state));
+            }
+            if state.done {
+                return None;
+            }
+            state.next_page().await;
+        }
+    })
+}
+
+// Cos 的配置, 未设置的项使用命令行的默认值
+pub struct CosBuilder
+{
+    folder: PathBuf,
+    session_file: PathBuf,
+    network: session::Network,
+    high_water: f64,
+    low_water: f64,
+    naming: Naming,
+    dedupe: bool,
+    concurrency: usize,
+    retries: usize,
+    dry_run: bool,
+    events: Events,
+}
+
+impl CosBuilder {
+    // 保存登录状态的 cookie 文件
+    pub fn session_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.session_file = path.into();
+        self
+    }
+
+    // 代理, 超时, CA 证书和 HTTP 版本, 对所有请求生效
+    pub fn network(mut self, network: session::Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    // 输出目录所在磁盘的使用率高低水位, 百分比, 高水位为 0 时不检查
+    pub fn disk_water(mut self, high: f64, low: f64) -> Self {
+        self.high_water = high;
+        self.low_water = low;
+        self
+    }
+
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    // 相同内容的文件硬链接到 .store
+    pub fn dedupe(mut self, on: bool) -> Self {
+        self.dedupe = on;
+        self
+    }
+
+    // 同时下载的文件数
+    pub fn concurrency(mut self, n: usize) -> Self {
+        self.concurrency = n.max(1);
+        self
+    }
+
+    // 单个文件下载出错后的重试次数, 每次等待 2 秒乘以已重试次数, 默认不重试
+    pub fn retries(mut self, n: usize) -> Self {
+        self.retries = n;
+        self
+    }
+
+    // 只生成下载计划, 不创建目录和文件
+    pub fn dry_run(mut self, on: bool) -> Self {
+        self.dry_run = on;
+        self
+    }
+
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Cos> {
+        let session = session::Session::with_network(self.session_file, &self.network).map_err(|e|{
+            anyhow::anyhow!("{}", e).context("Cos create session error!")
+        })?;
+        let folder = self.folder;
+        let downloader = Downloader {
+            session: session.clone(),
+            store: if self.dedupe { Some(Store::new(&folder)) } else { None },
+            quarantine: Quarantine::new(&folder),
+            limit: Arc::new(Semaphore::new(self.concurrency)),
+            concurrency: self.concurrency,
+            retries: self.retries,
+            events: self.events.clone(),
+        };
+        let (seen, partial) = downloaded_posts(&folder);
+        Ok(Cos {
+            http_request: session,
+            seen,
+            partial,
+            disk_guard: DiskGuard::new(vec![folder.clone()], self.high_water, self.low_water),
+            folder,
+            naming: self.naming,
+            downloader,
+            plan: if self.dry_run { Some(Plan::default()) } else { None },
+            events: self.events,
+        })
+    }
+}
+
+pub struct Cos
+{
+    // Http Client
+    http_request: session::Session,
+    // 保存文件夹
+    folder: PathBuf,
+    // 磁盘空间检查
+    disk_guard: DiskGuard,
+    // 帖子目录命名策略
+    naming: Naming,
+    // 文件下载, 所有标签共用
+    downloader: Downloader,
+    // 已下载的帖子 URL 与目录, 跨标签去重
+    seen: HashMap<String, PathBuf>,
+    // 之前的运行中未下载完成的帖子 URL 与目录, 在原目录中继续下载
+    partial: HashMap<String, PathBuf>,
+    // --dry-run 时记录下载计划, 不创建目录和文件
+    plan: Option<Plan>,
+    events: Events,
+}
+
+impl Cos {
+
+    pub fn builder(folder: impl Into<PathBuf>) -> CosBuilder {
+        CosBuilder {
+            folder: folder.into(),
+            session_file: PathBuf::from("./session.json"),
+            network: session::Network::default(),
+            high_water: 90.0,
+            low_water: 80.0,
+            naming: Naming::default(),
+            dedupe: true,
+            concurrency: 2,
+            retries: 0,
+            dry_run: false,
+            events: Events::default(),
+        }
+    }
+
+    pub fn session(&self) -> &session::Session {
+        &self.http_request
+    }
+
+    pub fn take_plan(&mut self) -> Option<Plan> {
+        self.plan.take()
+    }
+
+    // 帖子已下载过, 计入计划并输出事件
+    fn skip_downloaded(&mut self, label: &str, url: &str, detail: &str) {
+        self.events.emit(Event::PostSkipped { listing: label.to_string(), url: url.to_string(), rule: "downloaded".to_string(), detail: detail.to_string() });
+        if let Some(plan) = &mut self.plan {
+            plan.current().skipped += 1;
+        }
+    }
+
+    // 按命名策略确定帖子目录, 帖子已下载过时返回 None
+    fn post_dir(&self, item: &CosItem, tag: &str, id: &str, date: Option<&str>) -> Option<PathBuf> {
+        let fields = NameFields { tag, date, id, title: &item.title, suffix: None };
+        let dir = self.folder.join(self.naming.render(&fields));
+        if !dir.exists() {
+            return Some(dir);
+        }
+        if self.naming.has_id() || same_post(&dir, &item.url) {
+            warn!("dir {} is exist, skip this page!", dir.display());
+            return None;
+        }
+        // 不同帖子标题相同, 追加帖子 ID
+        let dir = self.folder.join(self.naming.render(&NameFields { suffix: Some(id), ..fields }));
+        if dir.exists() {
+            warn!("dir {} is exist, skip this page!", dir.display());
+            return None;
+        }
+        Some(dir)
+    }
+
+    // 被筛选规则跳过的帖子追加到输出目录 skipped.jsonl
+    fn record_skip(&mut self, tag: &str, item: &CosItem, skip: &Skip) {
+        info!("<{}> ==> skip {} by {}: {}", tag, &item.url, skip.rule, &skip.detail);
+        self.events.emit(Event::PostSkipped { listing: tag.to_string(), url: item.url.clone(), rule: skip.rule.to_string(), detail: skip.detail.clone() });
+        if let Some(plan) = &mut self.plan {
+            plan.current().filtered += 1;
+            return;
+        }
+        let record = serde_json::json!({
+            "time": std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            "tag": tag,
+            "url": &item.url,
+            "title": &item.title,
+            "date": &item.date,
+            "rule": skip.rule,
+            "detail": &skip.detail,
+        });
+        let res = std::fs::create_dir_all(&self.folder).and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.folder.join(SKIPPED_FILE))
+                .and_then(|mut f| writeln!(f, "{}", record))
+        });
+        if let Err(e) = res {
+            warn!("record skipped post {} error: {}", &item.url, e);
+        }
+    }
+
+    // 磁盘使用率超过高水位时暂停, 等待 cosdup 压缩上传后回收空间
+    async fn wait_for_disk(&self) {
+        if self.plan.is_some() {
+            return;
+        }
+        if let Some((path, usage)) = self.disk_guard.over_high() {
+            warn!("disk usage of {} is {:.1}%, pause downloading", path.display(), usage);
+            // 已清理帖子的数据仍留在存储中, 先回收
+            while !self.prune_store() && !self.disk_guard.below_low() {
+                tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+            }
+            info!("disk space reclaimed, resume downloading");
+        }
+    }
+
+    // 回收存储中未被引用的数据, 回收后低于低水位时返回 true
+    fn prune_store(&self) -> bool {
+        match &self.downloader.store {
+            Some(store) => store.prune().0 > 0 && self.disk_guard.below_low(),
+            None => false
+        }
+    }
+
+    // 标签或搜索结果中的帖子流, 标题和日期已按 filter 检查
+    pub fn posts(&self, listing: &Listing, filter: &Filter) -> impl Stream<Item = Listed> {
+        list(self.http_request.clone(), listing.clone(), filter.clone())
+    }
+
+    // 获取标签每页所有项目
+    pub async fn produce_by_page(&mut self, tag: &str, filter: &Filter) -> TagSummary {
+        self.produce(&Listing::Tag(tag.to_string()), filter).await
+    }
+
+    // 获取站内搜索结果每页所有项目
+    pub async fn produce_by_search(&mut self, query: &str, filter: &Filter) -> TagSummary {
+        self.produce(&Listing::Search(query.to_string()), filter).await
+    }
+
+    async fn produce(&mut self, listing: &Listing, filter: &Filter) -> TagSummary {
+        let tag = listing.name();
+        let tag = tag.as_str();
+        let mut summary = TagSummary { tag: tag.to_string(), ..Default::default() };
+        if let Some(plan) = &mut self.plan {
+            plan.begin(tag);
+        }
+        let mut item_list: VecDeque<CosItem> = VecDeque::new();
+        let mut posts = std::pin::pin!(self.posts(listing, filter));
+        while let Some(listed) = posts.next().await {
+            match listed {
+                Listed::Page { page, url, found } => {
+                    summary.pages += 1;
+                    self.events.emit(Event::PageFetched { listing: tag.to_string(), page, url: url.clone(), posts: found });
+                    if let Some(plan) = &mut self.plan {
+                        plan.current().pages.push(PagePlan { page, url, found, queued: 0 });
+                    }
+                }
+                Listed::Post(item) => {
+                    self.discovered(tag, &item);
+                    if let Some(page) = self.plan.as_mut().and_then(|p| p.current().pages.last_mut()) {
+                        page.queued += 1;
+                    }
+                    item_list.push_back(item);
+                }
+                Listed::Filtered(item, skip) => {
+                    self.discovered(tag, &item);
+                    self.record_skip(tag, &item, &skip);
+                    summary.filtered += 1;
+                }
+                Listed::PageFailed { page, url, error } => {
+                    self.events.emit(Event::PageFailed { listing: tag.to_string(), page, url, error });
+                }
+                Listed::Failed(e) => {
+                    error!("<{}> ==> {}", tag, e);
+                    self.events.emit(Event::ListingFailed { listing: tag.to_string(), error: e });
+                    summary.failed += 1;
+                }
+            }
+        }
+        summary.posts = item_list.len() + summary.filtered;
+        self.item_process(&mut item_list, Some(tag), filter, &mut summary).await;
+        summary
+    }
+
+    fn discovered(&self, tag: &str, item: &CosItem) {
+        self.events.emit(Event::PostDiscovered {
+            listing: tag.to_string(),
+            url: item.url.clone(),
+            title: item.title.clone(),
+            date: item.date.clone(),
+        });
+    }
+
+    // 直接下载指定的帖子, 标题和目录从详情页中确定
+    pub async fn get(&mut self, urls: &[String], filter: &Filter) -> TagSummary {
+        let mut summary = TagSummary { tag: GET_LABEL.to_string(), ..Default::default() };
+        if let Some(plan) = &mut self.plan {
+            plan.begin(GET_LABEL);
+        }
+        let mut item_list: VecDeque<CosItem> = urls.iter()
+            .map(|u| CosItem::new(String::new(), u.clone()))
+            .collect();
+        for item in &item_list {
+            self.discovered(GET_LABEL, item);
+        }
+        summary.posts = item_list.len();
+        self.item_process(&mut item_list, None, filter, &mut summary).await;
+        summary
+    }
+
+    // 依次爬取多个标签和搜索, 共用会话和下载并发数
+    pub async fn crawl(&mut self, listings: &[Listing], filter: &Filter) -> Vec<TagSummary> {
+        let mut summaries = Vec::with_capacity(listings.len());
+        for (i, listing) in listings.iter().enumerate() {
+            let tag = listing.name();
+            info!("<{}> ==> start listing {}/{}", tag, i + 1, listings.len());
+            let summary = match listing {
+                Listing::Tag(t) => self.produce_by_page(t, filter).await,
+                Listing::Search(q) => self.produce_by_search(q, filter).await,
+            };
+            info!("<{}> ==> {} posts, {} downloaded, {} skipped, {} failed, {} files",
+                tag, summary.posts, summary.downloaded, summary.skipped, summary.failed, summary.files);
+            summaries.push(summary);
+        }
+        summaries
+    }
+
+    // tag 为 None 时按帖子分类确定目录
+    async fn item_process(&mut self, item_list: &mut VecDeque<CosItem>, tag: Option<&str>, filter: &Filter, summary: &mut TagSummary) {
+        let label = tag.unwrap_or(GET_LABEL);
+        let total = item_list.len();
+        self.events.emit(Event::Queued { listing: label.to_string(), posts: total });
+        while !item_list.is_empty() {
+            let item = item_list.pop_front().unwrap();
+            info!("<{}> ==> post {}/{}: {}", label, total - item_list.len(), total, &item.title);
+            // 其他标签或之前的运行中已下载
+            if let Some(dir) = self.seen.get(&item.url) {
+                info!("<{}> ==> {} already downloaded in {}, skip", label, &item.url, dir.display());
+                let detail = format!("already downloaded in {}", dir.display());
+                summary.skipped += 1;
+                self.skip_downloaded(label, &item.url, &detail);
+                continue;
+            }
+            self.wait_for_disk().await;
+            // 初始化目录, 模板需要发布日期或没有标签和标题时在解析详情页后确定
+            let id = naming::post_id(&item.url);
+            let mut dir = self.partial.remove(&item.url);
+            if let Some(dir) = &dir {
+                info!("<{}> ==> {} not complete in {}, resume", label, &item.url, dir.display());
+            }
+            if let (true, Some(tag), false, false) = (dir.is_none(), tag, self.naming.needs_date(), item.title.is_empty()) {
+                dir = self.post_dir(&item, tag, &id, None);
+                if dir.is_none() {
+                    summary.skipped += 1;
+                    self.skip_downloaded(label, &item.url, "dir exists");
+                    continue;
+                }
+            }
+            let mut detail = match self.fetch_post(&item.url, &item.title, tag.unwrap_or_default()).await {
+                Ok(detail) => detail,
+                Err(e) => {
+                    error!("<{}> ==> item_process {:#}", label, e);
+                    self.events.emit(Event::PostFailed {
+                        listing: label.to_string(),
+                        url: item.url.clone(),
+                        dir: dir.as_ref().map(|d| d.display().to_string()),
+                        error: format!("{:#}", e),
+                    });
+                    summary.failed += 1;
+                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                    continue;
+                }
+            };
+            // 列表页没有的标题和日期按详情页检查, 图片数量和媒体类型只有详情页中有
+            let title_skip = if item.title.is_empty() { filter.check_title(&detail.meta.title) } else { None };
+            let skip = title_skip
+                .or_else(|| filter.check_date(detail.meta.date.as_deref()))
+                .or_else(|| filter.check_media(detail.imgs.len(), detail.videos.len()));
+            if let Some(skip) = skip {
+                let item = CosItem { title: detail.meta.title.clone(), date: detail.meta.date.clone(), ..item };
+                self.record_skip(label, &item, &skip);
+                summary.filtered += 1;
+                continue;
+            }
+            if !filter.media.images() {
+                detail.imgs.clear();
+            }
+            if !filter.media.videos() {
+                detail.videos.clear();
+            }
+            if tag.is_none() {
+                detail.meta.tag = detail.meta.categories.first().cloned().unwrap_or_else(|| GET_TAG.to_string());
+            }
+            let item = CosItem::new(detail.meta.title.clone(), item.url);
+            let dir = match dir.or_else(|| self.post_dir(&item, &detail.meta.tag, &id, detail.meta.date.as_deref())) {
+                Some(dir) => dir,
+                None => {
+                    summary.skipped += 1;
+                    self.skip_downloaded(label, &item.url, "dir exists");
+                    continue;
+                }
+            };
+            if self.plan.is_some() {
+                self.plan_post(&item, &dir, &mut detail).await;
+                summary.downloaded += 1;
+                self.seen.insert(item.url.clone(), dir);
+                continue;
+            }
+            let requested = self.download_post(&dir, &mut detail).await;
+            summary.downloaded += 1;
+            summary.files += detail.meta.files.len();
+            summary.missing += requested.saturating_sub(detail.meta.files.len());
+            summary.bytes += detail.meta.files.iter().map(|f| f.size).sum::<u64>();
+            self.seen.insert(item.url.clone(), dir);
+            // 延时
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        }
+    }
+
+    // 获取并解析帖子详情页, title 为空时取详情页标题, tag 为空时由调用方按分类确定
+    pub async fn fetch_post(&self, url: &str, title: &str, tag: &str) -> anyhow::Result<PostDetail> {
+        let res = self.http_request.http_get(url).await
+            .map_err(|e| anyhow::anyhow!("get http request error: {}", e))?;
+        let html = self.http_request.text(res).await
+            .map_err(|e| anyhow::anyhow!("get response text error: {}", e))?;
+        let html = Vis::load(html)
+            .map_err(|e| anyhow::anyhow!("parse html error: {}", e))?;
+        Ok(post::parse(&html, title, url, tag))
+    }
+
+    // 下载帖子中的图片和视频到 dir, meta.json 记录 URL 与本地文件名, 返回需要下载的文件数
+    pub async fn download_post(&self, dir: &Path, detail: &mut PostDetail) -> usize {
+        let dir = dir.to_path_buf();
+        let wanted = detail.imgs.len() + detail.videos.len();
+        // 继续下载未完成的帖子时保留已下载的文件
+        let done = downloaded_files(&dir);
+        let in_sub = |sub: &str| done.iter().filter(|f| f.file.starts_with(&format!("{}/", sub))).cloned().collect::<Vec<_>>();
+        let (imgs_done, videos_done) = (in_sub("imgs"), in_sub("videos"));
+        detail.meta.files = done;
+        detail.meta.complete = false;
+        write_meta(&dir, &detail.meta).await;
+        detail.meta.files.clear();
+        let mut imgs_vec: VecDeque<String> = VecDeque::with_capacity(detail.imgs.len());
+        for c in std::mem::take(&mut detail.imgs) {
+            match media::resolve(&self.http_request, &c).await {
+                Some(url) => imgs_vec.push_back(url),
+                None => warn!("<{}> ==> no image url in {:?}", &detail.meta.tag, c)
+            }
+        }
+        let video_vec: VecDeque<String> = std::mem::take(&mut detail.videos).into();
+        let requested = imgs_vec.len() + video_vec.len();
+        self.events.emit(Event::PostStarted {
+            url: detail.meta.url.clone(),
+            title: detail.meta.title.clone(),
+            dir: dir.display().to_string(),
+            images: imgs_vec.len(),
+            videos: video_vec.len(),
+        });
+        let mut h1 : Option<JoinHandle<_>> = None;
+        let mut h2 : Option<JoinHandle<_>> = None;
+        if !imgs_vec.is_empty() {
+            let dl = self.downloader.clone();
+            let dir0 = dir.join("imgs");
+            h1 = Some(tokio::spawn(async move {
+                download_files(dl, "img", dir0, imgs_vec, imgs_done).await
+            }));
+        }
+        if !video_vec.is_empty() {
+            let dl = self.downloader.clone();
+            let dir1 = dir.join("videos");
+            h2 = Some(tokio::spawn(async move {
+                download_files(dl, "video", dir1, video_vec, videos_done).await
+            }));
+        }
+        // 记录 URL 与本地文件名的对应关系
+        for h in [h1, h2].into_iter().flatten() {
+            if let Ok(files) = h.await {
+                detail.meta.files.extend(files);
+            }
+        }
+        // 解析失败的图片和下载失败的文件都会使帖子保持未完成
+        detail.meta.complete = detail.meta.files.len() >= wanted;
+        if !detail.meta.complete {
+            warn!("{} has {}/{} files, download again in next run", dir.display(), detail.meta.files.len(), wanted);
+        }
+        write_meta(&dir, &detail.meta).await;
+        self.events.emit(Event::PostCompleted {
+            url: detail.meta.url.clone(),
+            dir: dir.display().to_string(),
+            files: detail.meta.files.len(),
+            bytes: detail.meta.files.iter().map(|f| f.size).sum(),
+        });
+        requested
+    }
+
+    // 解析图片地址并用 HEAD 请求估算大小, 计入下载计划
+    async fn plan_post(&mut self, item: &CosItem, dir: &Path, detail: &mut PostDetail) {
+        let mut images = Vec::with_capacity(detail.imgs.len());
+        for c in std::mem::take(&mut detail.imgs) {
+            match media::resolve(&self.http_request, &c).await {
+                Some(url) => images.push(plan::media_plan(&self.http_request, url).await),
+                None => warn!("no image url in {:?}", c)
+            }
+        }
+        let mut videos = Vec::with_capacity(detail.videos.len());
+        for url in std::mem::take(&mut detail.videos) {
+            videos.push(plan::media_plan(&self.http_request, url).await);
+        }
+        let bytes = images.iter().chain(videos.iter()).filter_map(|m| m.size).sum();
+        info!("plan {}: {} images, {} videos, {} bytes", dir.display(), images.len(), videos.len(), bytes);
+        self.events.emit(Event::PostPlanned {
+            url: item.url.clone(),
+            dir: dir.display().to_string(),
+            files: images.len() + videos.len(),
+            bytes,
+        });
+        if let Some(plan) = &mut self.plan {
+            plan.add_post(PostPlan {
+                url: item.url.clone(),
+                title: item.title.clone(),
+                date: detail.meta.date.clone(),
+                dir: dir.display().to_string(),
+                images,
+                videos,
+                bytes,
+            });
+        }
+    }
+
+    // 登陆获取session
+    #[allow(dead_code)]
+    pub async fn login(&mut self) ->bool {
+        self.http_request.login("<username>", "<password>").await
+    }
+
+    #[allow(dead_code)]
+    pub async fn logout(&mut self) {
+        self.http_request.logout().await
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, date: Option<&str>) -> CosItem {
+        CosItem { title: title.to_string(), url: format!("https://a.com/{}.html", title), date: date.map(|d| d.to_string()) }
+    }
+
+    fn rules(queue: &VecDeque<Listed>) -> Vec<&str> {
+        queue.iter().map(|l| match l {
+            Listed::Post(_) => "post",
+            Listed::Filtered(_, skip) => skip.rule,
+            _ => "other",
+        }).collect()
+    }
+
+    #[test]
+    fn older_posts_stop_tag_listings_only() {
+        let filter = Filter { since: Some("2023-03-01".to_string()), until: Some("2023-03-31".to_string()), ..Default::default() };
+        let items = || vec![item("a", Some("2023-04-02")), item("b", Some("2023-03-15")), item("c", None), item("d", Some("2023-02-28"))];
+
+        let mut queue = VecDeque::new();
+        assert!(queue_items(&Listing::Tag("cos".to_string()), &filter, items(), &mut queue));
+        assert_eq!(rules(&queue), ["until", "post", "post", "since"]);
+
+        let mut queue = VecDeque::new();
+        assert!(!queue_items(&Listing::Search("cos".to_string()), &filter, items(), &mut queue));
+        assert_eq!(rules(&queue), ["until", "post", "post", "since"]);
+
+        // 窗口内和晚于窗口的帖子不停止翻页
+        let mut queue = VecDeque::new();
+        assert!(!queue_items(&Listing::Tag("cos".to_string()), &filter, items().into_iter().take(3).collect(), &mut queue));
+        assert!(!queue_items(&Listing::Tag("cos".to_string()), &Filter::default(), items(), &mut queue));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::media::{self, META_FILES, SNIFF_LEN};
+use log::{info, warn};
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// 输出目录下的隔离目录
+pub const FAILED_DIR: &str = "failed";
+
+// 检查文件是否为完整的媒体文件, 返回失败原因
+// expected_len 为响应的 Content-Length
+pub fn check_file(path: &Path, expected_len: Option<u64>) -> Result<(), String> {
+    let size = std::fs::metadata(path).map_err(|e| format!("stat error: {}", e))?.len();
+    if size == 0 {
+        return Err("empty file".to_string());
+    }
+    if let Some(len) = expected_len {
+        if size != len {
+            return Err(format!("truncated: {} of {} bytes", size, len));
+        }
+    }
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)
+        .and_then(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut head))
+        .map_err(|e| format!("read error: {}", e))?;
+    let actual = match media::sniff_ext(&head) {
+        Some(ext) => ext,
+        None if looks_like_html(&head) => return Err("html or text page instead of media".to_string()),
+        None => return Err("unknown file type".to_string()),
+    };
+    let claimed = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let claimed = if claimed == "jpeg" { "jpg".to_string() } else { claimed };
+    if media::is_media_ext(&claimed) && family(&claimed) != family(&actual) {
+        return Err(format!("claimed {} but content is {}", claimed, actual));
+    }
+    // 能解码的图片完整解码, 发现截断和损坏
+    if DECODABLE.contains(&actual.as_str()) {
+        let img = image::io::Reader::open(path)
+            .map_err(|e| format!("open error: {}", e))?
+            .with_guessed_format()
+            .map_err(|e| format!("read error: {}", e))?;
+        img.decode().map_err(|e| format!("decode error: {}", e))?;
+    }
+    Ok(())
+}
+
+// 启用了解码器的图片格式
+const DECODABLE: [&str; 5] = ["jpg", "png", "gif", "webp", "bmp"];
+
+// 文件头无法区分的格式视为同一类
+fn family(ext: &str) -> &str {
+    match ext {
+        "mkv" => "webm",
+        "m4v" | "mov" => "mp4",
+        e => e,
+    }
+}
+
+fn looks_like_html(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(head).trim_start().to_lowercase();
+    text.starts_with("<!doctype") || text.starts_with("<html") || text.starts_with("<?xml") || text.starts_with('{')
+}
+
+// 隔离记录, 追加到 failed/index.jsonl
+#[derive(Debug, Serialize)]
+struct FailedRecord<'a>
+{
+    // 原路径, 相对输出目录
+    file: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    reason: &'a str,
+    // unix 秒
+    time: u64,
+}
+
+// 把无效文件移入 <root>/failed/ 下相同的相对路径, 记录原因
+#[derive(Debug, Clone)]
+pub struct Quarantine
+{
+    root: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new(root: &Path) -> Self {
+        Self { root: root.to_path_buf() }
+    }
+
+    pub fn put(&self, path: &Path, url: Option<&str>, reason: &str) -> anyhow::Result<PathBuf> {
+        let rel = path.strip_prefix(&self.root).unwrap_or(path);
+        let rel = rel.strip_prefix("/").unwrap_or(rel);
+        let failed = self.root.join(FAILED_DIR);
+        let dst = failed.join(rel);
+        if let Some(p) = dst.parent() {
+            std::fs::create_dir_all(p)?;
+        }
+        if std::fs::rename(path, &dst).is_err() {
+            std::fs::copy(path, &dst)?;
+            std::fs::remove_file(path)?;
+        }
+        let record = FailedRecord {
+            file: &rel.to_string_lossy(),
+            url,
+            reason,
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(failed.join("index.jsonl"))?
+            .write_all(line.as_bytes())?;
+        warn!("quarantined {}: {}", path.display(), reason);
+        Ok(dst)
+    }
+}
+
+// 输出目录下不属于帖子的目录
+pub fn is_reserved(name: &std::ffi::OsStr) -> bool {
+    name == FAILED_DIR || name == crate::store::STORE_DIR
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyStats
+{
+    pub checked: usize,
+    pub failed: usize,
+}
+
+// 重新检查输出目录中已下载的媒体文件, 无效文件移入 failed/
+pub fn verify_library(root: &Path) -> VerifyStats {
+    let quarantine = Quarantine::new(root);
+    let mut stats = VerifyStats::default();
+    let walker = WalkDir::new(root).into_iter()
+        .filter_entry(|e| e.depth() != 1 || !is_reserved(e.file_name()));
+    let files: Vec<PathBuf> = walker.filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy();
+            !name.starts_with('.') && !META_FILES.contains(&name.as_ref())
+        })
+        // 只检查帖子的 imgs 和 videos 目录
+        .filter(|e| e.path().parent().and_then(|p| p.file_name()).is_some_and(|n| n == "imgs" || n == "videos"))
+        .map(|e| e.into_path())
+        .collect();
+    for path in files {
+        stats.checked += 1;
+        if let Err(reason) = check_file(&path, None) {
+            stats.failed += 1;
+            let url = meta_url(&path);
+            if let Err(e) = quarantine.put(&path, url.as_deref(), &reason) {
+                warn!("quarantine {} error: {}", path.display(), e);
+                continue;
+            }
+            // 帖子标记为未完成, 下次运行重新下载
+            if let Err(e) = drop_from_meta(&path) {
+                warn!("update meta.json for {} error: {}", path.display(), e);
+            }
+        }
+    }
+    info!("checked {} files, {} quarantined", stats.checked, stats.failed);
+    stats
+}
+
+// 帖子目录的 meta.json 和文件在其中记录的相对路径, 如 imgs/001_foo.jpg
+fn meta_entry(path: &Path) -> Option<(PathBuf, String)> {
+    let sub = path.parent()?;
+    let post = sub.parent()?;
+    let file = format!("{}/{}", sub.file_name()?.to_string_lossy(), path.file_name()?.to_string_lossy());
+    Some((post.join("meta.json"), file))
+}
+
+fn is_entry(f: &serde_json::Value, file: &str) -> bool {
+    f.get("file").and_then(|v| v.as_str()) == Some(file)
+}
+
+// 从帖子 meta.json 中查找文件对应的 URL
+fn meta_url(path: &Path) -> Option<String> {
+    let (meta_path, file) = meta_entry(path)?;
+    let meta: serde_json::Value = serde_json::from_slice(&std::fs::read(meta_path).ok()?).ok()?;
+    meta.get("files")?.as_array()?.iter()
+        .find(|f| is_entry(f, &file))
+        .and_then(|f| f.get("url")?.as_str().map(|s| s.to_string()))
+}
+
+// 从 meta.json 中删除被隔离的文件并标记帖子未完成, 没有 meta.json 时忽略
+fn drop_from_meta(path: &Path) -> anyhow::Result<()> {
+    let (meta_path, file) = match meta_entry(path) {
+        Some(entry) if entry.0.exists() => entry,
+        _ => return Ok(()),
+    };
+    let mut meta: serde_json::Value = serde_json::from_slice(&std::fs::read(&meta_path)?)?;
+    let map = match meta.as_object_mut() {
+        Some(map) => map,
+        None => return Ok(()),
+    };
+    if let Some(files) = map.get_mut("files").and_then(|v| v.as_array_mut()) {
+        files.retain(|f| !is_entry(f, &file));
+    }
+    map.insert("complete".to_string(), false.into());
+    std::fs::write(&meta_path, serde_json::to_vec_pretty(&meta)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cosjun-check-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn encode(format: image::ImageOutputFormat) -> Vec<u8> {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 128])));
+        let mut data = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn check_file_reasons() {
+        let dir = temp_dir("file");
+        let jpg = encode(image::ImageOutputFormat::Jpeg(90));
+        let png = encode(image::ImageOutputFormat::Png);
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+
+        assert_eq!(check_file(&write("ok.jpg", &jpg), Some(jpg.len() as u64)), Ok(()));
+        assert_eq!(check_file(&write("ok.jpeg", &jpg), None), Ok(()));
+        assert_eq!(check_file(&write("empty.jpg", b""), None), Err("empty file".to_string()));
+        assert_eq!(check_file(&write("page.jpg", b"\n<!DOCTYPE html><html><body>404</body></html>"), None),
+            Err("html or text page instead of media".to_string()));
+        assert_eq!(check_file(&write("short.jpg", &jpg), Some(jpg.len() as u64 + 100)),
+            Err(format!("truncated: {} of {} bytes", jpg.len(), jpg.len() + 100)));
+        assert_eq!(check_file(&write("png.jpg", &png), None), Err("claimed jpg but content is png".to_string()));
+        assert_eq!(check_file(&write("noise.bin", &[7u8; 64]), None), Err("unknown file type".to_string()));
+        let cut = check_file(&write("cut.jpg", &jpg[..jpg.len() / 2]), None).unwrap_err();
+        assert!(cut.starts_with("decode error"), "{}", cut);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_library_marks_post_incomplete() {
+        let root = temp_dir("library");
+        let post = root.join("tag").join("title");
+        std::fs::create_dir_all(post.join("imgs")).unwrap();
+        std::fs::write(post.join("imgs").join("001_a.jpg"), encode(image::ImageOutputFormat::Jpeg(90))).unwrap();
+        std::fs::write(post.join("imgs").join("002_b.jpg"), b"<html>error</html>").unwrap();
+        let meta = serde_json::json!({
+            "title": "title",
+            "files": [
+                { "index": 1, "url": "https://a.com/a.jpg", "file": "imgs/001_a.jpg", "size": 1 },
+                { "index": 2, "url": "https://a.com/b.jpg", "file": "imgs/002_b.jpg", "size": 1 },
+            ],
+            "complete": true,
+        });
+        std::fs::write(post.join("meta.json"), serde_json::to_vec(&meta).unwrap()).unwrap();
+
+        let stats = verify_library(&root);
+        assert_eq!((stats.checked, stats.failed), (2, 1));
+        assert!(root.join(FAILED_DIR).join("tag/title/imgs/002_b.jpg").exists());
+        let index = std::fs::read_to_string(root.join(FAILED_DIR).join("index.jsonl")).unwrap();
+        assert!(index.contains("\"url\":\"https://a.com/b.jpg\""));
+        let meta: serde_json::Value = serde_json::from_slice(&std::fs::read(post.join("meta.json")).unwrap()).unwrap();
+        assert_eq!(meta["complete"], false);
+        let files: Vec<_> = meta["files"].as_array().unwrap().iter().map(|f| f["file"].as_str().unwrap()).collect();
+        assert_eq!(files, ["imgs/001_a.jpg"]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
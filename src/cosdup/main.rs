@@ -1,185 +1,281 @@
-use walkdir::WalkDir;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use log::{error, info};
 
-fn create_dirs(dir: &PathBuf) ->bool {
-    if dir.exists() {
-        return true;
-    }
-    match std::fs::create_dir_all(dir) {
-        Ok(_) => true,
-        Err(e) => {
-            error!("create dir error: {}", e.to_string());
-            false
-        }
-    }
-}
+use cosjun_pull::dup::crypt::{self, Encryption};
+use cosjun_pull::dup::limiter::RateLimiter;
+use cosjun_pull::dup::{manifest, Dup, Retention};
+use cosjun_pull::event::{Event, Events};
+use cosjun_pull::report;
 
-struct Dup
+// 命令行参数
+struct Options
 {
-    // 已下载完成目录
-    downloaded_vec: Vec<PathBuf>,
-    // 下载完成后压缩目录
-    zip_path: PathBuf,
-    // 最多下载几个目录
-    chunk_size: usize,
+    positional: Vec<String>,
+    retention: Retention,
+    workers: usize,
+    rate: u32,
+    // 使用 COSDUP_PASSPHRASE 口令加密
+    passphrase: bool,
+    recipients: Vec<String>,
+    // 每行一个公钥的文件
+    recipients_file: Option<PathBuf>,
+    identity: Option<PathBuf>,
+    // 磁盘使用率高低水位, 百分比
+    high_water: f64,
+    low_water: f64,
+    // 只输出分块和压缩包名称
+    dry_run: bool,
+    // 输出 NDJSON 事件
+    json: bool,
+    // 运行报告写入压缩目录的格式
+    save_report: Option<report::Format>,
 }
 
-impl Dup {
-    fn new(zip_path: PathBuf, chunk_size: usize) -> Self {
-        Self { downloaded_vec: Vec::with_capacity(40), zip_path, chunk_size }
-    }
-
-    fn start_download(self: &mut Self, root_dir: &str) {
-        let mut start_index = 0;
-        let mut end_index =0;
-        for entry in WalkDir::new(root_dir)
-                .into_iter() {
-            if let Err(_) = entry {
-                continue;
-            }
-            let entry = entry.unwrap();
-            if entry.file_name() != "info.txt" {
-                continue;
-            }
-            let entry = entry.into_path();
-            info!("find info.txt in : {}", entry.to_str().unwrap());
-            // 查找一级父目录
-            if let Some(p1) = entry.parent() {
-                if p1.exists() && p1.is_dir() {
-                    let p1 = p1.to_path_buf();
-                    // 查找二级父目录
-                    if let Some(p2) = p1.parent() {
-                        if Dup::download(p1.clone()) {
-                            self.downloaded_vec.push(p2.to_path_buf());
-                            end_index += 1;
-                            if self.downloaded_vec.len() >= self.chunk_size {
-                                let filename = format!("cos_{}_{}-{}.tar.gz", 
-                                    PathBuf::from(root_dir).file_name().unwrap().to_str().unwrap(),
-                                    start_index, end_index);
-                                if self.compress_downloaded(&filename) {
-                                    end_index += 1;
-                                    start_index = end_index;
-                                }
-                            }
-                        }
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut opts = Options {
+            positional: Vec::new(),
+            retention: Retention::Uploaded,
+            workers: 3,
+            rate: 6,
+            passphrase: false,
+            recipients: Vec::new(),
+            recipients_file: None,
+            identity: None,
+            high_water: 90.0,
+            low_water: 80.0,
+            dry_run: false,
+            json: false,
+            save_report: None,
+        };
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            let mut value = || it.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--retention" => {
+                    opts.retention = Retention::parse(&value()?)
+                        .ok_or("--retention must be one of: keep, uploaded")?;
+                },
+                "--workers" => {
+                    opts.workers = value()?.parse().ok().filter(|n| *n > 0)
+                        .ok_or("--workers must be a positive number")?;
+                },
+                "--rate" => {
+                    opts.rate = value()?.parse()
+                        .map_err(|_| "--rate must be a number of downloads per minute, 0 for unlimited")?;
+                },
+                "--encrypt" => {
+                    if value()? != "passphrase" {
+                        return Err("--encrypt only supports: passphrase".to_string());
                     }
-                }
+                    opts.passphrase = true;
+                },
+                "--recipient" => opts.recipients.push(value()?),
+                "--recipients-file" => opts.recipients_file = Some(PathBuf::from(value()?)),
+                "--identity" => opts.identity = Some(PathBuf::from(value()?)),
+                "--high-water" | "--low-water" => {
+                    let v = value()?.parse::<f64>().ok().filter(|v| (0.0..=100.0).contains(v))
+                        .ok_or_else(|| format!("{} must be a disk usage percent in 0-100, 0 disables the check", arg))?;
+                    if arg == "--high-water" {
+                        opts.high_water = v;
+                    } else {
+                        opts.low_water = v;
+                    }
+                },
+                "--dry-run" => opts.dry_run = true,
+                "--output" => {
+                    opts.json = match value()?.as_str() {
+                        "text" => false,
+                        "json" => true,
+                        _ => return Err("--output must be one of: text, json".to_string()),
+                    };
+                },
+                "--save-report" => {
+                    opts.save_report = Some(report::Format::parse(&value()?)
+                        .ok_or("--save-report must be one of: json, md, both")?);
+                },
+                s => opts.positional.push(s.to_string()),
             }
-            std::thread::sleep(std::time::Duration::from_secs(10));
         }
-        let filename = format!("cos_{}_{}-{}.tar.gz", 
-            PathBuf::from(root_dir).file_name().unwrap().to_str().unwrap(),
-            start_index, end_index
-        );
-        self.compress_downloaded(&filename);
+        Ok(opts)
     }
 
-    fn download(path: PathBuf) -> bool {
-        info!("start download files in dir: {}", path.to_str().unwrap());
-        let cmd = format!("cd \"{}\" && wget -nc -c -t 5 -T 120 -i \"info.txt\"", path.to_str().unwrap());
-        // info!("run command: {}", &cmd);
-        match Command::new("/bin/sh")
-            .arg("-c")
-            .arg(&cmd)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn() {
-                Ok(mut child) => {
-                    match child.wait() {
-                        Ok(status) => status.success(),
-                        Err(_) => false
-                    }
-                },
-                Err(e) => {
-                    error!("run command {} error: {}", &cmd, e.to_string());
-                    false
-                }
+    // 打包时使用的加密配置, 公钥加密不需要私钥, 有 --identity 时重新读取压缩包校验
+    fn encryption(&self) -> anyhow::Result<Option<Encryption>> {
+        let mut recipients = self.recipients.clone();
+        if let Some(path) = &self.recipients_file {
+            recipients.extend(Encryption::read_recipients(path)?);
+            if recipients.is_empty() {
+                anyhow::bail!("no recipient in {}", path.display());
             }
+        }
+        if self.passphrase && !recipients.is_empty() {
+            anyhow::bail!("--encrypt passphrase and --recipient can not be used together");
+        }
+        if self.passphrase {
+            return Ok(Some(Encryption::passphrase_from_env()?));
+        }
+        if recipients.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Encryption::recipients(&recipients, self.identity.as_deref())?))
     }
 
-    fn compress_downloaded(self: &mut Self, filename: &str) -> bool {
-        info!("start compress files in dir: {}", self.zip_path.join(filename).to_str().unwrap());
-        let mut cmd = format!("tar zcf \"{}\"", self.zip_path.join(filename).to_str().unwrap());
-        for dir in &self.downloaded_vec {
-            cmd += &format!(" \"{}\"", dir.to_str().unwrap());
+    // 解密使用的配置, 有 --identity 时用私钥, 否则读取环境变量中的口令
+    fn decryption_config(&self) -> anyhow::Result<Encryption> {
+        match &self.identity {
+            Some(p) => Encryption::identities(p),
+            None => Encryption::passphrase_from_env()
+                .map_err(|e| e.context("decrypt needs --identity <file> or a passphrase")),
         }
-        let mut result = create_dirs(&self.zip_path);
-        if result {
-            result =  match Command::new("/bin/sh")
-                .arg("-c")
-                .arg(&cmd)
-                .stdout(Stdio::null())
-                .spawn() {
-                    Ok(mut child) => {
-                        match child.wait() {
-                            Ok(status) => status.success(),
-                            Err(_) => false
-                        }
-                    },
-                    Err(e) => {
-                        error!("run command {} error: {}", &cmd, e.to_string());
-                        false
-                    }
-            };
+    }
+
+    // 校验压缩包时使用的配置, 未加密的压缩包不需要
+    fn decryption(&self, archive: &Path) -> anyhow::Result<Option<Encryption>> {
+        if !crypt::is_encrypted(archive) {
+            return Ok(None);
         }
-        if result {
-            info!("compress files success, rm src files");
-            for it in &self.downloaded_vec {
-                let _ = std::fs::remove_dir_all(it);
-            }
-            if self.upload(self.zip_path.join(filename)) {
-                info!("upload file: {} success!", self.zip_path.join(filename).to_str().unwrap());
+        self.decryption_config().map(Some)
+    }
+}
+
+fn verify_archives(opts: &Options, archives: &[String]) -> bool {
+    let mut ok = true;
+    for a in archives {
+        let path = Path::new(a);
+        match opts.decryption(path).and_then(|enc| manifest::verify(path, enc.as_ref())) {
+            Ok(m) => info!("{} ok, {} posts, {} files", a, m.posts.len(),
+                m.posts.iter().map(|p| p.files.len()).sum::<usize>()),
+            Err(e) => {
+                error!("{} verify failed: {:#}", a, e);
+                ok = false;
             }
-        }else {
-            warn!("compress file error!");
         }
-        self.downloaded_vec.clear();
-        result
     }
+    ok
+}
 
-    fn upload(self: &Self, path: PathBuf) -> bool {
-        // use https://github.com/aoaostar/alidrive-uploader
-        let cmd = format!("alidrive -c ./alidrive.yaml {} CosJun/zips", 
-            path.to_str().unwrap()
-        );
-        info!("upload file use: {}", &cmd);
-        // info!("run command: {}", &cmd);
-        match Command::new("/bin/sh")
-            .arg("-c")
-            .arg(&cmd)
-            .stdout(Stdio::null())
-            .spawn() {
-                Ok(mut child) => {
-                    match child.wait() {
-                        Ok(status) => status.success(),
-                        Err(_) => false
-                    }
-                },
-                Err(e) => {
-                    error!("run command {} error: {}", &cmd, e.to_string());
-                    false
-                }
-            }
+fn decrypt_archive(opts: &Options, input: &str, output: Option<&String>) -> bool {
+    let input = Path::new(input);
+    let output = match output {
+        Some(o) => PathBuf::from(o),
+        None if crypt::is_encrypted(input) => input.with_extension(""),
+        None => {
+            error!("{} is not an .age file, give an output path", input.display());
+            return false;
+        }
+    };
+    let enc = match opts.decryption_config() {
+        Ok(enc) => enc,
+        Err(e) => {
+            error!("{:#}", e);
+            return false;
+        }
+    };
+    match crypt::decrypt_file(input, &output, &enc) {
+        Ok(n) => {
+            info!("decrypt {} to {}, {} bytes", input.display(), output.display(), n);
+            true
+        },
+        Err(e) => {
+            error!("decrypt {} error: {:#}", input.display(), e);
+            false
+        }
+    }
+}
+
+// 输出运行报告, 指定格式时写入压缩目录
+fn print_report(opts: &Options, report: &report::Report, zip: &Path) {
+    if opts.json {
+        Events::ndjson().emit(Event::Report(serde_json::json!(report)));
+    } else {
+        report.print();
+    }
+    if let Some(format) = opts.save_report {
+        match report.write(zip, format) {
+            Ok(paths) => paths.iter().for_each(|p| info!("report: {}", p.display())),
+            Err(e) => error!("write report to {} error: {}", zip.display(), e),
+        }
     }
 }
 
+fn usage() {
+    println!("cosdup <src> <target> [--retention keep|uploaded] [--workers N] [--rate N]");
+    println!("       [--encrypt passphrase | --recipient <age1...> | --recipients-file <file>] [--identity <file>]");
+    println!("       [--high-water 90] [--low-water 80] [--dry-run] [--output text|json] [--save-report json|md|both]");
+    println!("cosdup verify <archive>... [--identity <file>]");
+    println!("cosdup decrypt <archive.age> [<out>] [--identity <file>]");
+    println!("passphrase is read from env {}", crypt::PASSPHRASE_ENV);
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let opts = match Options::parse(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("{}", e);
+            usage();
+            return;
+        }
+    };
+    // JSON 输出时 stdout 只有事件, 日志写到 stderr
     let mut logger_builder = env_logger::Builder::from_default_env();
-    logger_builder.target(env_logger::Target::Stdout);
+    logger_builder.target(if opts.json { env_logger::Target::Stderr } else { env_logger::Target::Stdout });
     logger_builder.filter_level(log::LevelFilter::Info);
     logger_builder.init();
+    let positional = &opts.positional;
 
-    let args: Vec<String> = std::env::args().collect();
-
-    // cosdup <src> <out>
-    if args.len() == 3 {
-        // 指定压缩目录和下载最大目录数量，太大占有磁盘空间
-        let mut dup = Dup::new(PathBuf::from(args.get(2).unwrap().as_str()), 40);
-        // 指定下载目录
-        dup.start_download(args.get(1).unwrap().as_str());
-    }else {
-        println!("cosdup <src> <target>");
+    match positional.first().map(|s| s.as_str()) {
+        // cosdup verify <archive>...
+        Some("verify") if positional.len() >= 2 => {
+            if !verify_archives(&opts, &positional[1..]) {
+                std::process::exit(1);
+            }
+        },
+        // cosdup decrypt <archive.age> [out]
+        Some("decrypt") if (2..=3).contains(&positional.len()) => {
+            if !decrypt_archive(&opts, &positional[1], positional.get(2)) {
+                std::process::exit(1);
+            }
+        },
+        // cosdup <src> <out>
+        Some(_) if positional.len() == 2 => {
+            let encryption = match opts.encryption() {
+                Ok(enc) => enc,
+                Err(e) => {
+                    error!("{:#}", e);
+                    return;
+                }
+            };
+            let recorder = report::Recorder::new("cosdup");
+            // 指定压缩目录和下载最大目录数量，太大占有磁盘空间
+            let mut dup = match Dup::builder(&positional[0], &positional[1])
+                .chunk_size(40)
+                .retention(opts.retention)
+                .encryption(encryption)
+                .disk_water(opts.high_water, opts.low_water)
+                .dry_run(opts.dry_run)
+                .events(recorder.wrap(if opts.json { Events::ndjson() } else { Events::default() }))
+                .build() {
+                Ok(dup) => dup,
+                Err(e) => {
+                    error!("{:#}", e);
+                    return;
+                }
+            };
+            if opts.dry_run {
+                let plan = dup.plan();
+                if opts.json {
+                    Events::ndjson().emit(Event::Plan(serde_json::json!(plan)));
+                } else {
+                    plan.print();
+                }
+                return;
+            }
+            // 指定下载目录
+            dup.start_download(opts.workers, &RateLimiter::per_minute(opts.rate));
+            print_report(&opts, &recorder.report(), Path::new(&positional[1]));
+        },
+        _ => usage(),
     }
-}
\ No newline at end of file
+}
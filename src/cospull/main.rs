@@ -1,36 +1,550 @@
-use log::{error};
+use log::{error, info};
 use std::path::PathBuf;
+use std::sync::Arc;
 
-mod api;
-mod session;
+use cosjun_pull::{api, check, filter, naming, report, session, similar, store};
+use cosjun_pull::event::{Event, Events};
 
-async fn pull(tag: &str, output: &str)
+mod progress;
+
+// 下载相关的设置
+struct Settings
 {
     // 爬取文件输出目录
-    let mut cos = api::Cos::new(PathBuf::from(output)).unwrap();
+    output: String,
+    high_water: f64,
+    low_water: f64,
+    naming: naming::Naming,
+    dedupe: bool,
+    concurrency: usize,
+    retries: usize,
+    network: session::Network,
+    // 只输出下载计划, 有路径时同时写入 JSON
+    dry_run: bool,
+    plan: Option<String>,
+    // 进度条, None 时输出 NDJSON 事件
+    progress: Option<Arc<progress::Progress>>,
+    // 统计运行报告, 有格式时同时写入输出目录
+    recorder: report::Recorder,
+    save_report: Option<report::Format>,
+}
+
+impl Settings {
+    fn cos(&self) -> anyhow::Result<api::Cos> {
+        api::Cos::builder(&self.output)
+            .disk_water(self.high_water, self.low_water)
+            .naming(self.naming.clone())
+            .dedupe(self.dedupe)
+            .concurrency(self.concurrency)
+            .retries(self.retries)
+            .network(self.network.clone())
+            .dry_run(self.dry_run)
+            .events(self.recorder.wrap(match &self.progress {
+                Some(p) => p.events(),
+                None => Events::ndjson(),
+            }))
+            .build()
+    }
+
+    // 输出下载计划或下载结果
+    fn report(&self, cos: &mut api::Cos, summaries: &[api::TagSummary]) {
+        let json = self.progress.is_none();
+        if let Some(p) = &self.progress {
+            p.finish();
+        }
+        let plan = match cos.take_plan() {
+            Some(plan) => plan,
+            None => return self.run_report(summaries, json),
+        };
+        if json {
+            Events::ndjson().emit(Event::Plan(serde_json::json!(plan)));
+        } else {
+            plan.print();
+        }
+        if let Some(path) = &self.plan {
+            match plan.write(std::path::Path::new(path)) {
+                Ok(_) => info!("plan: {}", path),
+                Err(e) => error!("write plan {} error: {}", path, e),
+            }
+        }
+    }
+
+    // 每个标签的汇总和整个运行的报告
+    fn run_report(&self, summaries: &[api::TagSummary], json: bool) {
+        let report = self.recorder.report();
+        if json {
+            let events = Events::ndjson();
+            for s in summaries {
+                events.emit(Event::Summary(serde_json::json!(s)));
+            }
+            events.emit(Event::Report(serde_json::json!(report)));
+        } else {
+            print_summary(summaries);
+            report.print();
+        }
+        if let Some(format) = self.save_report {
+            match report.write(std::path::Path::new(&self.output), format) {
+                Ok(paths) => paths.iter().for_each(|p| info!("report: {}", p.display())),
+                Err(e) => error!("write report to {} error: {}", self.output, e),
+            }
+        }
+    }
+}
+
+async fn pull(listings: &[api::Listing], filter: &filter::Filter, settings: &Settings)
+{
+    let mut cos = match settings.cos() {
+        Ok(cos) => cos,
+        Err(e) => return error!("{:#}", e),
+    };
+    if cos.login().await {
+        let summaries = cos.crawl(listings, filter).await;
+        settings.report(&mut cos, &summaries);
+    }else {
+        error!("login error! More infomation: https://www.cosjun.cn");
+    }
+}
+
+fn print_summary(summaries: &[api::TagSummary]) {
+    println!("{:<24} {:>6} {:>6} {:>10} {:>8} {:>8} {:>7} {:>7} {:>8} {:>12}",
+        "tag", "pages", "posts", "downloaded", "skipped", "filtered", "failed", "files", "missing", "bytes");
+    for s in summaries {
+        println!("{:<24} {:>6} {:>6} {:>10} {:>8} {:>8} {:>7} {:>7} {:>8} {:>12}",
+            s.tag, s.pages, s.posts, s.downloaded, s.skipped, s.filtered, s.failed, s.files, s.missing, s.bytes);
+    }
+}
+
+// 直接下载指定的帖子
+async fn get(urls: &[String], filter: &filter::Filter, settings: &Settings)
+{
+    let mut cos = match settings.cos() {
+        Ok(cos) => cos,
+        Err(e) => return error!("{:#}", e),
+    };
     if cos.login().await {
-        cos.produce_by_page(tag, -1).await;
+        let summary = cos.get(urls, filter).await;
+        settings.report(&mut cos, &[summary]);
     }else {
         error!("login error! More infomation: https://www.cosjun.cn");
     }
 }
 
+// 标签或 URL 列表文件, 每行一项, # 开头为注释
+fn read_list(path: &str) -> anyhow::Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("{}", e).context(format!("read list file {} error", path)))?;
+    Ok(text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// 合并输出目录中已下载的重复文件
+fn dedupe(output: &str) {
+    let output = PathBuf::from(output);
+    if !output.is_dir() {
+        println!("{} is not a directory", output.display());
+        return;
+    }
+    let stats = store::Store::new(&output).dedupe(&output);
+    println!("scanned {} files, linked {} duplicates, saved {} bytes", stats.files, stats.linked, stats.saved);
+    println!("pruned {} unreferenced blobs, freed {} bytes", stats.pruned, stats.freed);
+}
+
+// 重新检查已下载的媒体文件, 无效文件移入 failed/
+fn verify(output: &str) {
+    let output = PathBuf::from(output);
+    if !output.is_dir() {
+        println!("{} is not a directory", output.display());
+        return;
+    }
+    let stats = check::verify_library(&output);
+    println!("checked {} files, {} invalid moved to {}", stats.checked, stats.failed, output.join(check::FAILED_DIR).display());
+}
+
+// 感知哈希查找相似图片, 输出 JSON 与 HTML 报告
+fn similar(output: &str, kind: similar::HashKind, threshold: u32, report: Option<&str>) {
+    let output = PathBuf::from(output);
+    if !output.is_dir() {
+        println!("{} is not a directory", output.display());
+        return;
+    }
+    let result = similar::scan(&output, kind, threshold);
+    let prefix = report.map(PathBuf::from).unwrap_or_else(|| output.join("similar"));
+    match similar::write_report(&result, &prefix) {
+        Ok((json, html)) => {
+            println!("found {} clusters in {} images", result.clusters.len(), result.scanned);
+            println!("report: {} {}", json.display(), html.display());
+        }
+        Err(e) => error!("write similar report error: {}", e),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let mut logger_builder = env_logger::Builder::from_default_env();
-    logger_builder.target(env_logger::Target::Stdout);
-    logger_builder.filter_level(log::LevelFilter::Info);
-    logger_builder.init();
-
-    // cospull <tag> output
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 3 {
+    // cospull <tag>... output [--tags-file FILE] [--search QUERY] [--from-page N] [--to-page N] [--since D] [--until D]
+    //     [--include RE] [--exclude RE] [--min-images N] [--max-images N] [--media images|videos|both] [--concurrency 2] [--retries 0] [--high-water 90] [--low-water 80] [--name-template T] [--name-max-bytes N] [--no-dedupe]
+    //     [--dry-run] [--plan plan.json] [--output text|json] [--save-report json|md|both]
+    //     [--proxy URL] [--connect-timeout S] [--read-timeout S] [--timeout S] [--ca-cert PEM] [--http auto|1.1|2]
+    // cospull get <url>... output [--from-file urls.txt]
+    // cospull dedupe output
+    // cospull verify output
+    // cospull similar output [--hash phash] [--threshold 8] [--report prefix]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut positional: Vec<&str> = Vec::new();
+    let mut high_water = 90.0;
+    let mut low_water = 80.0;
+    let mut template = naming::DEFAULT_TEMPLATE.to_string();
+    let mut max_bytes = naming::DEFAULT_MAX_BYTES;
+    let mut dedupe_files = true;
+    let mut hash_kind = similar::HashKind::Perceptual;
+    let mut threshold = similar::DEFAULT_THRESHOLD;
+    let mut report: Option<String> = None;
+    let mut tags_files: Vec<String> = Vec::new();
+    let mut concurrency = 2;
+    let mut retries = 0;
+    let mut url_files: Vec<String> = Vec::new();
+    let mut searches: Vec<String> = Vec::new();
+    let mut filter = filter::Filter::default();
+    let mut dry_run = false;
+    let mut plan_file: Option<String> = None;
+    let mut json_output = false;
+    let mut save_report: Option<report::Format> = None;
+    let mut network = session::Network::default();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--name-template" => match it.next() {
+                Some(t) => template = t.clone(),
+                None => {
+                    println!("--name-template needs a value such as {{tag}}/{{date}}-{{id}}-{{title}}");
+                    return;
+                }
+            },
+            "--name-max-bytes" => match it.next().and_then(|s| s.parse().ok()) {
+                Some(n) => max_bytes = n,
+                None => {
+                    println!("--name-max-bytes must be a number");
+                    return;
+                }
+            },
+            "--high-water" | "--low-water" => {
+                let v = match it.next().and_then(|s| s.parse::<f64>().ok()) {
+                    Some(v) if (0.0..=100.0).contains(&v) => v,
+                    _ => {
+                        println!("{} must be a disk usage percent in 0-100, 0 disables the check", arg);
+                        return;
+                    }
+                };
+                if arg == "--high-water" {
+                    high_water = v;
+                } else {
+                    low_water = v;
+                }
+            },
+            "--no-dedupe" => dedupe_files = false,
+            "--dry-run" => dry_run = true,
+            "--output" => match it.next().map(|s| s.as_str()) {
+                Some("text") => json_output = false,
+                Some("json") => json_output = true,
+                _ => {
+                    println!("--output must be one of text, json");
+                    return;
+                }
+            },
+            "--proxy" => match it.next() {
+                Some(p) => network.proxy = Some(p.clone()),
+                None => {
+                    println!("--proxy needs a url such as http://host:port or socks5://host:port");
+                    return;
+                }
+            },
+            "--connect-timeout" | "--read-timeout" | "--timeout" => {
+                let t = match it.next().and_then(|s| s.parse::<f64>().ok()) {
+                    Some(t) if t > 0.0 => std::time::Duration::from_secs_f64(t),
+                    _ => {
+                        println!("{} must be a positive number of seconds", arg);
+                        return;
+                    }
+                };
+                match arg.as_str() {
+                    "--connect-timeout" => network.connect_timeout = Some(t),
+                    "--read-timeout" => network.read_timeout = Some(t),
+                    _ => network.timeout = Some(t),
+                }
+            },
+            "--ca-cert" => match it.next() {
+                Some(p) => network.ca_certs.push(PathBuf::from(p)),
+                None => {
+                    println!("--ca-cert needs a PEM file");
+                    return;
+                }
+            },
+            "--http" => match it.next().and_then(|s| session::HttpVersion::parse(s)) {
+                Some(v) => network.http = v,
+                None => {
+                    println!("--http must be one of auto, 1.1, 2");
+                    return;
+                }
+            },
+            "--save-report" => match it.next().and_then(|s| report::Format::parse(s)) {
+                Some(f) => save_report = Some(f),
+                None => {
+                    println!("--save-report must be one of json, md, both");
+                    return;
+                }
+            },
+            "--plan" => match it.next() {
+                Some(p) => {
+                    plan_file = Some(p.clone());
+                    dry_run = true;
+                }
+                None => {
+                    println!("--plan needs a json file path");
+                    return;
+                }
+            },
+            "--tags-file" => match it.next() {
+                Some(f) => tags_files.push(f.clone()),
+                None => {
+                    println!("--tags-file needs a file with one tag per line");
+                    return;
+                }
+            },
+            "--from-page" | "--to-page" => {
+                let v = match it.next().and_then(|s| s.parse::<i32>().ok()) {
+                    Some(v) if v > 0 => v,
+                    _ => {
+                        println!("{} must be a page number starting from 1", arg);
+                        return;
+                    }
+                };
+                if arg == "--from-page" {
+                    filter.from_page = v;
+                } else {
+                    filter.to_page = v;
+                }
+            },
+            "--since" | "--until" => {
+                let v = match it.next().and_then(|s| filter::parse_date(s)) {
+                    Some(v) => v,
+                    None => {
+                        println!("{} must be a date such as 2023-01-31", arg);
+                        return;
+                    }
+                };
+                if arg == "--since" {
+                    filter.since = Some(v);
+                } else {
+                    filter.until = Some(v);
+                }
+            },
+            "--include" | "--exclude" => {
+                let re = match it.next().map(|s| regex::Regex::new(s)) {
+                    Some(Ok(re)) => re,
+                    Some(Err(e)) => {
+                        println!("{} regex error: {}", arg, e);
+                        return;
+                    }
+                    None => {
+                        println!("{} needs a regex on post titles", arg);
+                        return;
+                    }
+                };
+                if arg == "--include" {
+                    filter.include.push(re);
+                } else {
+                    filter.exclude.push(re);
+                }
+            },
+            "--min-images" | "--max-images" => {
+                let v = match it.next().and_then(|s| s.parse::<usize>().ok()) {
+                    Some(v) => v,
+                    None => {
+                        println!("{} must be a number", arg);
+                        return;
+                    }
+                };
+                if arg == "--min-images" {
+                    filter.min_images = Some(v);
+                } else {
+                    filter.max_images = Some(v);
+                }
+            },
+            "--media" => match it.next().and_then(|s| filter::MediaKind::parse(s)) {
+                Some(m) => filter.media = m,
+                None => {
+                    println!("--media must be one of images, videos, both");
+                    return;
+                }
+            },
+            "--search" => match it.next() {
+                Some(q) if !q.trim().is_empty() => searches.push(q.trim().to_string()),
+                _ => {
+                    println!("--search needs a query such as a coser or character name");
+                    return;
+                }
+            },
+            "--from-file" => match it.next() {
+                Some(f) => url_files.push(f.clone()),
+                None => {
+                    println!("--from-file needs a file with one post url per line");
+                    return;
+                }
+            },
+            "--concurrency" => match it.next().and_then(|s| s.parse::<usize>().ok()) {
+                Some(n) if n > 0 => concurrency = n,
+                _ => {
+                    println!("--concurrency must be a positive number");
+                    return;
+                }
+            },
+            "--retries" => match it.next().and_then(|s| s.parse::<usize>().ok()) {
+                Some(n) => retries = n,
+                None => {
+                    println!("--retries must be a number, 0 disables retries");
+                    return;
+                }
+            },
+            "--hash" => match it.next().and_then(|s| similar::HashKind::parse(s)) {
+                Some(k) => hash_kind = k,
+                None => {
+                    println!("--hash must be one of ahash, dhash, phash");
+                    return;
+                }
+            },
+            "--threshold" => match it.next().and_then(|s| s.parse::<u32>().ok()) {
+                Some(t) if t <= 64 => threshold = t,
+                _ => {
+                    println!("--threshold must be a hamming distance in 0-64");
+                    return;
+                }
+            },
+            "--report" => match it.next() {
+                Some(r) => report = Some(r.clone()),
+                None => {
+                    println!("--report needs a path prefix");
+                    return;
+                }
+            },
+            s => positional.push(s),
+        }
+    }
+    // JSON 输出时 stdout 只有事件, 日志写到 stderr
+    // 否则日志经过进度条输出, 避免打断进度条
+    let progress = if json_output {
+        let mut logger_builder = env_logger::Builder::from_default_env();
+        logger_builder.target(env_logger::Target::Stderr);
+        logger_builder.filter_level(log::LevelFilter::Info);
+        logger_builder.init();
+        None
+    } else {
+        Some(progress::Progress::init())
+    };
+
+    if filter.to_page != -1 && filter.to_page < filter.from_page {
+        println!("--to-page {} is before --from-page {}", filter.to_page, filter.from_page);
+        return;
+    }
+    if let (Some(min), Some(max)) = (filter.min_images, filter.max_images) {
+        if min > max {
+            println!("--min-images {} is more than --max-images {}", min, max);
+            return;
+        }
+    }
+    if let (Some(since), Some(until)) = (&filter.since, &filter.until) {
+        if since > until {
+            println!("--since {} is after --until {}", since, until);
+            return;
+        }
+    }
+    let naming = match naming::Naming::new(&template, max_bytes) {
+        Ok(n) => n,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if positional.len() == 2 && positional[0] == "dedupe" {
+        dedupe(positional[1]);
+    }else if positional.len() == 2 && positional[0] == "verify" {
+        verify(positional[1]);
+    }else if positional.len() == 2 && positional[0] == "similar" {
+        similar(positional[1], hash_kind, threshold, report.as_deref());
+    }else if positional.first() == Some(&"get") && positional.len() >= 2 {
+        let (output, urls) = positional[1..].split_last().unwrap();
+        let mut urls: Vec<String> = urls.iter().map(|u| u.to_string()).collect();
+        for f in &url_files {
+            match read_list(f) {
+                Ok(u) => urls.extend(u),
+                Err(e) => {
+                    println!("{:#}", e);
+                    return;
+                }
+            }
+        }
+        if let Some(bad) = urls.iter().find(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+            println!("{} is not a post url", bad);
+            return;
+        }
+        let mut unique: Vec<String> = Vec::with_capacity(urls.len());
+        for u in urls {
+            if !unique.contains(&u) {
+                unique.push(u);
+            }
+        }
+        if unique.is_empty() {
+            println!("no post url to download");
+            return;
+        }
+        let settings = Settings { output: output.to_string(), high_water, low_water, naming, dedupe: dedupe_files, concurrency, retries, network: network.clone(), dry_run, plan: plan_file.clone(), progress: progress.clone(),
+            recorder: report::Recorder::new("cospull"), save_report };
+        get(&unique, &filter, &settings).await;
+    }else if positional.len() >= 2 || (positional.len() == 1 && (!tags_files.is_empty() || !searches.is_empty())) {
+        // 最后一个参数为输出目录, 其余为标签
+        let (output, tags) = positional.split_last().unwrap();
+        let mut tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        for f in &tags_files {
+            match read_list(f) {
+                Ok(t) => tags.extend(t),
+                Err(e) => {
+                    println!("{:#}", e);
+                    return;
+                }
+            }
+        }
+        let mut listings: Vec<api::Listing> = Vec::with_capacity(tags.len() + searches.len());
+        for t in tags {
+            if !listings.iter().any(|l| matches!(l, api::Listing::Tag(x) if *x == t)) {
+                listings.push(api::Listing::Tag(t));
+            }
+        }
+        for q in searches {
+            if !listings.iter().any(|l| matches!(l, api::Listing::Search(x) if *x == q)) {
+                listings.push(api::Listing::Search(q));
+            }
+        }
+        if listings.is_empty() {
+            println!("no tag or search to crawl");
+            return;
+        }
         // 开始下载
-        pull(
-            args.get(1).unwrap().as_str(), 
-            args.get(2).unwrap().as_str()
-        ).await;
+        let settings = Settings { output: output.to_string(), high_water, low_water, naming, dedupe: dedupe_files, concurrency, retries, network: network.clone(), dry_run, plan: plan_file.clone(), progress: progress.clone(),
+            recorder: report::Recorder::new("cospull"), save_report };
+        pull(&listings, &filter, &settings).await;
     }else {
-        println!("cospull <tag> <target>");
+        println!("cospull <tag>... <target> [--tags-file FILE] [--search QUERY] [--concurrency 2] [--retries 0] [--high-water 90] [--low-water 80]");
+        println!("        [--name-template {}] [--name-max-bytes {}] [--no-dedupe]", naming::DEFAULT_TEMPLATE, naming::DEFAULT_MAX_BYTES);
+        println!("        [--from-page N] [--to-page N] [--since YYYY-MM-DD] [--until YYYY-MM-DD]");
+        println!("        [--include REGEX] [--exclude REGEX] [--min-images N] [--max-images N] [--media images|videos|both]");
+        println!("        [--dry-run] [--plan plan.json] [--output text|json] [--save-report json|md|both]");
+        println!("        [--proxy http://host:port|socks5://host:port] [--connect-timeout S] [--read-timeout S] [--timeout S]");
+        println!("        [--ca-cert ca.pem]... [--http auto|1.1|2]");
+        println!("cospull get <post-url>... <target> [--from-file urls.txt]");
+        println!("cospull dedupe <target>");
+        println!("cospull verify <target>");
+        println!("cospull similar <target> [--hash phash|dhash|ahash] [--threshold {}] [--report <target>/similar]", similar::DEFAULT_THRESHOLD);
+        println!("name template fields: {{tag}} {{date}} {{id}} {{title}}");
     }
 }
@@ -0,0 +1,227 @@
+use cosjun_pull::event::{Event, Events};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// 整个运行共用的进度显示: 帖子总进度条, 每个正在下载的文件一个字节进度条, 底部为总字节数和耗时
+// stdout 不是终端时不画进度条, 每个帖子结束时输出一行文本
+pub struct Progress
+{
+    multi: MultiProgress,
+    tty: bool,
+    start: Instant,
+    // 开始爬取时才创建进度条
+    state: Mutex<Option<State>>,
+}
+
+struct State
+{
+    posts: ProgressBar,
+    footer: ProgressBar,
+    // 正在下载的文件和已计入总数的字节数, 按 (目录, URL) 区分, 不同帖子可能包含相同 URL
+    files: HashMap<(String, String), (ProgressBar, u64)>,
+    // 列表获取完毕后开始处理帖子, 此时跳过的帖子计入总进度
+    processing: bool,
+    bytes: u64,
+    completed: usize,
+    failed: usize,
+}
+
+// 同时记录日志和绘制进度条时, 先清除进度条再写日志
+struct BarLogger
+{
+    inner: env_logger::Logger,
+    multi: MultiProgress,
+}
+
+impl log::Log for BarLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.matches(record) {
+            self.multi.suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn file_style(total: bool) -> ProgressStyle {
+    let template = if total {
+        "  {wide_msg} {bytes:>10}/{total_bytes:<10} {bytes_per_sec:>12} eta {eta:>3} {bar:20.green/white}"
+    } else {
+        "  {spinner} {wide_msg} {bytes:>10} {bytes_per_sec:>12}"
+    };
+    ProgressStyle::with_template(template).unwrap().progress_chars("##-")
+}
+
+impl Progress {
+    // 安装经过进度条输出的日志, 日志级别与 env_logger 默认配置相同
+    pub fn init() -> Arc<Self> {
+        let tty = std::io::stdout().is_terminal();
+        let multi = if tty {
+            MultiProgress::new()
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+        let inner = env_logger::Builder::from_default_env()
+            .target(env_logger::Target::Stdout)
+            .filter_level(log::LevelFilter::Info)
+            .build();
+        log::set_max_level(inner.filter());
+        let _ = log::set_boxed_logger(Box::new(BarLogger { inner, multi: multi.clone() }));
+        Arc::new(Self { multi, tty, start: Instant::now(), state: Mutex::new(None) })
+    }
+
+    // 创建总进度条和底部总计, 返回更新进度条的事件回调
+    pub fn events(self: &Arc<Self>) -> Events {
+        let posts = self.multi.add(ProgressBar::new(0));
+        posts.set_style(ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>5}/{len:5} posts {wide_msg}",
+            ).unwrap().progress_chars("##-"));
+        let footer = self.multi.add(ProgressBar::new_spinner());
+        footer.set_style(ProgressStyle::with_template("{msg}").unwrap());
+        *self.state.lock().unwrap() = Some(State { posts, footer, files: HashMap::new(), processing: false, bytes: 0, completed: 0, failed: 0 });
+        let progress = Arc::clone(self);
+        Events::new(move |e| progress.on_event(e))
+    }
+
+    // 总字节数, 文件数, 平均速率和耗时
+    fn totals(&self, state: &State) -> String {
+        let secs = self.start.elapsed().as_secs_f64().max(0.001);
+        format!("{} in {} files ({} failed), {}/s, {}",
+            HumanBytes(state.bytes), state.completed, state.failed,
+            HumanBytes((state.bytes as f64 / secs) as u64), HumanDuration(self.start.elapsed()))
+    }
+
+    fn on_event(&self, e: &Event) {
+        let mut guard = self.state.lock().unwrap();
+        let state = match guard.as_mut() {
+            Some(state) => state,
+            None => return,
+        };
+        match e {
+            Event::PageFetched { listing, page, .. } => {
+                state.processing = false;
+                state.posts.set_message(format!("<{}> page {}", listing, page));
+            }
+            Event::Queued { listing, posts } => {
+                state.processing = true;
+                state.posts.inc_length(*posts as u64);
+                state.posts.set_message(format!("<{}>", listing));
+            }
+            Event::PostStarted { title, .. } => state.posts.set_message(title.clone()),
+            Event::PostSkipped { .. } if state.processing => state.posts.inc(1),
+            Event::PostFailed { .. } | Event::PostPlanned { .. } => state.posts.inc(1),
+            Event::PostCompleted { dir, files, bytes, .. } => {
+                state.posts.inc(1);
+                if !self.tty {
+                    println!("[{}/{}] {}: {} files, {}", state.posts.position(), state.posts.length().unwrap_or(0),
+                        dir, files, HumanBytes(*bytes));
+                }
+            }
+            Event::FileStarted { url, dir } => {
+                let pb = self.multi.insert_before(&state.footer, ProgressBar::new_spinner());
+                pb.set_style(file_style(false));
+                pb.set_message(url.rsplit('/').next().unwrap_or(url).to_string());
+                state.files.insert((dir.clone(), url.clone()), (pb, 0));
+            }
+            Event::FileProgress { url, dir, bytes, total } => {
+                if let Some((pb, counted)) = state.files.get_mut(&(dir.clone(), url.clone())) {
+                    if let (Some(total), None) = (total, pb.length()) {
+                        pb.set_length(*total);
+                        pb.set_style(file_style(true));
+                    }
+                    pb.set_position(*bytes);
+                    state.bytes += bytes.saturating_sub(*counted);
+                    *counted = *bytes;
+                }
+            }
+            // 重新下载, 已计入的字节数作废
+            Event::FileRetry { url, dir, .. } => {
+                if let Some((pb, counted)) = state.files.get_mut(&(dir.clone(), url.clone())) {
+                    state.bytes -= *counted;
+                    *counted = 0;
+                    pb.set_position(0);
+                }
+            }
+            Event::FileCompleted { url, dir, bytes, .. } => {
+                if let Some((pb, counted)) = state.files.remove(&(dir.clone(), url.clone())) {
+                    state.bytes += bytes.saturating_sub(counted);
+                    pb.finish_and_clear();
+                    self.multi.remove(&pb);
+                }
+                state.completed += 1;
+            }
+            Event::FileFailed { url, dir, .. } => {
+                if let Some((pb, _)) = state.files.remove(&(dir.clone(), url.clone())) {
+                    pb.finish_and_clear();
+                    self.multi.remove(&pb);
+                }
+                state.failed += 1;
+            }
+            _ => return,
+        }
+        let totals = self.totals(state);
+        state.footer.set_message(totals);
+    }
+
+    // 结束所有进度条, 输出总计
+    pub fn finish(&self) {
+        let guard = self.state.lock().unwrap();
+        let state = match guard.as_ref() {
+            Some(state) => state,
+            None => return,
+        };
+        state.posts.finish();
+        let totals = self.totals(state);
+        state.footer.finish_with_message(totals.clone());
+        if !self.tty {
+            println!("{}", totals);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_url_in_two_posts_keeps_separate_bars() {
+        let progress = Arc::new(Progress {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            tty: true,
+            start: Instant::now(),
+            state: Mutex::new(None),
+        });
+        let events = progress.events();
+        let s = |v: &str| v.to_string();
+        let url = s("https://a.com/same.jpg");
+        for dir in ["p1/imgs", "p2/imgs"] {
+            events.emit(Event::FileStarted { url: url.clone(), dir: s(dir) });
+        }
+        events.emit(Event::FileProgress { url: url.clone(), dir: s("p1/imgs"), bytes: 100, total: Some(300) });
+        events.emit(Event::FileProgress { url: url.clone(), dir: s("p2/imgs"), bytes: 50, total: None });
+        events.emit(Event::FileCompleted { url: url.clone(), dir: s("p1/imgs"), file: s("p1/imgs/001_same.jpg"), bytes: 300, duration_ms: 1 });
+        {
+            let guard = progress.state.lock().unwrap();
+            let state = guard.as_ref().unwrap();
+            assert_eq!(state.bytes, 350);
+            let (pb, counted) = &state.files[&(s("p2/imgs"), url.clone())];
+            assert_eq!((pb.position(), *counted), (50, 50));
+            assert_eq!(state.files.len(), 1);
+        }
+        events.emit(Event::FileRetry { url: url.clone(), dir: s("p2/imgs"), attempt: 1, error: s("timeout") });
+        events.emit(Event::FileFailed { url, dir: s("p2/imgs"), error: s("timeout"), duration_ms: 1 });
+        let guard = progress.state.lock().unwrap();
+        let state = guard.as_ref().unwrap();
+        assert!(state.files.is_empty());
+        assert_eq!((state.bytes, state.completed, state.failed), (300, 1, 1));
+    }
+}
@@ -0,0 +1,67 @@
+// cospull 与 cosdup 共用的磁盘空间检查
+use std::path::{Path, PathBuf};
+
+// 磁盘使用率, 百分比
+pub fn usage(path: &Path) -> std::io::Result<f64> {
+    let stats = fs2::statvfs(existing_ancestor(path))?;
+    if stats.total_space() == 0 {
+        return Ok(0.0);
+    }
+    let used = stats.total_space().saturating_sub(stats.available_space());
+    Ok(used as f64 * 100.0 / stats.total_space() as f64)
+}
+
+// 可用字节数
+pub fn available(path: &Path) -> std::io::Result<u64> {
+    fs2::available_space(existing_ancestor(path))
+}
+
+// 目录可能还未创建, 取最近的已存在上级目录
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.exists())
+        .unwrap_or(Path::new("."))
+}
+
+// 使用率超过高水位时暂停, 回落到低水位以下后继续
+#[derive(Debug, Clone)]
+pub struct DiskGuard
+{
+    paths: Vec<PathBuf>,
+    high_water: f64,
+    low_water: f64,
+}
+
+impl DiskGuard {
+    pub fn new(paths: Vec<PathBuf>, high_water: f64, low_water: f64) -> Self {
+        Self { paths, high_water, low_water: low_water.min(high_water) }
+    }
+
+    // 高水位为 0 时不检查
+    pub fn enabled(&self) -> bool {
+        self.high_water > 0.0
+    }
+
+    // 返回超过高水位的目录及使用率
+    pub fn over_high(&self) -> Option<(PathBuf, f64)> {
+        self.over(self.high_water)
+    }
+
+    pub fn below_low(&self) -> bool {
+        self.over(self.low_water).is_none()
+    }
+
+    fn over(&self, limit: f64) -> Option<(PathBuf, f64)> {
+        if !self.enabled() {
+            return None;
+        }
+        for p in &self.paths {
+            match usage(p) {
+                Ok(u) if u >= limit => return Some((p.clone(), u)),
+                Ok(_) => {}
+                Err(e) => log::warn!("check disk usage of {} error: {}", p.display(), e),
+            }
+        }
+        None
+    }
+}
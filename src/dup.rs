@@ -0,0 +1,835 @@
+use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use log::{error, info, warn};
+
+pub mod archive;
+pub mod crypt;
+pub mod journal;
+pub mod limiter;
+pub mod manifest;
+
+use crate::disk::{self, DiskGuard};
+use crate::event::{self, Event, Events};
+use crate::store::Store;
+use crypt::Encryption;
+use journal::{Journal, Stage};
+use limiter::RateLimiter;
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+fn create_dirs(dir: &PathBuf) ->bool {
+    if dir.exists() {
+        return true;
+    }
+    match std::fs::create_dir_all(dir) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("create dir error: {}", e.to_string());
+            false
+        }
+    }
+}
+
+// 源目录保留策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    // 始终保留源目录
+    Keep,
+    // 压缩包校验通过且上传成功后删除源目录
+    Uploaded,
+}
+
+impl Retention {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "keep" => Some(Retention::Keep),
+            "uploaded" => Some(Retention::Uploaded),
+            _ => None,
+        }
+    }
+}
+
+pub struct Dup
+{
+    // 已下载完成目录
+    downloaded_vec: Vec<PathBuf>,
+    // 下载根目录
+    root_dir: PathBuf,
+    // 下载完成后压缩目录
+    zip_path: PathBuf,
+    // 最多下载几个目录
+    chunk_size: usize,
+    // 源目录保留策略
+    retention: Retention,
+    // 任务日志
+    journal: Journal,
+    // 压缩包加密配置
+    encryption: Option<Encryption>,
+    // 磁盘空间检查
+    disk_guard: DiskGuard,
+    // cospull 的去重存储, 清理源目录后回收不再被引用的数据
+    store: Option<Store>,
+    events: Events,
+}
+
+// Dup 的配置, 未设置的项使用命令行的默认值
+pub struct DupBuilder
+{
+    root_dir: PathBuf,
+    zip_path: PathBuf,
+    chunk_size: usize,
+    retention: Retention,
+    encryption: Option<Encryption>,
+    high_water: f64,
+    low_water: f64,
+    dry_run: bool,
+    events: Events,
+}
+
+impl DupBuilder {
+    // 每个压缩包最多包含的目录数
+    pub fn chunk_size(mut self, n: usize) -> Self {
+        self.chunk_size = n.max(1);
+        self
+    }
+
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn encryption(mut self, encryption: Option<Encryption>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    // 下载和压缩目录所在磁盘的使用率高低水位, 百分比, 高水位为 0 时不检查
+    pub fn disk_water(mut self, high: f64, low: f64) -> Self {
+        self.high_water = high;
+        self.low_water = low;
+        self
+    }
+
+    // 不创建压缩目录, 只用于 plan()
+    pub fn dry_run(mut self, on: bool) -> Self {
+        self.dry_run = on;
+        self
+    }
+
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Dup> {
+        if !self.dry_run && !create_dirs(&self.zip_path) {
+            anyhow::bail!("create zip dir {} error", self.zip_path.display());
+        }
+        let journal = Journal::load(self.zip_path.join(format!("cosdup_{}.journal.json", Dup::tag_of(&self.root_dir))))?;
+        let disk_guard = DiskGuard::new(vec![self.root_dir.clone(), self.zip_path.clone()], self.high_water, self.low_water);
+        let store = Store::locate(&self.root_dir);
+        Ok(Dup {
+            downloaded_vec: Vec::with_capacity(self.chunk_size),
+            root_dir: self.root_dir,
+            zip_path: self.zip_path,
+            chunk_size: self.chunk_size,
+            retention: self.retention,
+            journal,
+            encryption: self.encryption,
+            disk_guard,
+            store,
+            events: self.events,
+        })
+    }
+}
+
+// dry-run 时的打包计划
+#[derive(Debug, Default, serde::Serialize)]
+pub struct DupPlan
+{
+    // 已压缩未上传, 将重新上传的压缩包
+    pub upload: Vec<String>,
+    // 已上传未清理的目录
+    pub clean: Vec<String>,
+    pub archives: Vec<ArchivePlan>,
+    pub dirs: usize,
+    pub urls: usize,
+    // 目录当前占用的字节数
+    pub bytes: u64,
+    pub chunk_size: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ArchivePlan
+{
+    pub archive: String,
+    pub dirs: Vec<DirPlan>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DirPlan
+{
+    pub dir: String,
+    pub index: u64,
+    pub urls: usize,
+    pub bytes: u64,
+    // 之前已下载, 等待压缩
+    pub downloaded: bool,
+}
+
+impl DupPlan {
+    pub fn print(&self) {
+        for filename in &self.upload {
+            println!("upload {} (archived before)", filename);
+        }
+        if !self.clean.is_empty() {
+            println!("clean {} dirs of uploaded archives", self.clean.len());
+        }
+        for a in &self.archives {
+            println!("{} ({} dirs)", a.archive, a.dirs.len());
+            for d in &a.dirs {
+                println!("  #{:<6} {:>5} urls {:>12} bytes{}  {}", d.index, d.urls, d.bytes,
+                    if d.downloaded { " downloaded" } else { "" }, d.dir);
+            }
+        }
+        println!("{} dirs, {} urls, {} bytes on disk, {} archives of up to {} dirs",
+            self.dirs, self.urls, self.bytes, self.archives.len(), self.chunk_size);
+    }
+}
+
+// meta.json 中的文件记录
+#[derive(serde::Deserialize)]
+struct MetaFile
+{
+    url: String,
+    // 相对帖子目录的路径
+    file: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PostFiles
+{
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    files: Vec<MetaFile>,
+}
+
+// 等待磁盘空间回收的检查间隔
+const DISK_WAIT_MIN: Duration = Duration::from_secs(10);
+const DISK_WAIT_MAX: Duration = Duration::from_secs(300);
+
+// 每个文件最多调用 wget 的次数
+const WGET_TRIES: usize = 5;
+
+// 下载线程发给压缩线程的消息
+enum Done
+{
+    // 帖子目录下载结束, 是否成功
+    Post(PathBuf, bool),
+    // 磁盘使用率超过高水位, 需要提前压缩上传, 空间回收后回复
+    DiskFull(mpsc::Sender<()>),
+}
+
+impl Dup {
+    pub fn builder(root_dir: impl Into<PathBuf>, zip_path: impl Into<PathBuf>) -> DupBuilder {
+        DupBuilder {
+            root_dir: root_dir.into(),
+            zip_path: zip_path.into(),
+            chunk_size: 40,
+            retention: Retention::Uploaded,
+            encryption: None,
+            high_water: 90.0,
+            low_water: 80.0,
+            dry_run: false,
+            events: Events::default(),
+        }
+    }
+
+    fn tag_of(root_dir: &Path) -> String {
+        root_dir.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+    }
+
+    // 目录在任务日志中的名称
+    fn folder_name(&self, dir: &Path) -> String {
+        archive::entry_name(&self.root_dir, dir).to_string_lossy().to_string()
+    }
+
+    fn folder_path(&self, name: &str) -> PathBuf {
+        self.root_dir.parent().unwrap_or(&self.root_dir).join(name)
+    }
+
+    // 压缩包名称取首尾目录序号
+    fn archive_name(&self, indices: &[u64]) -> String {
+        format!("cos_{}_{}-{}.tar.gz{}",
+            Dup::tag_of(&self.root_dir),
+            indices.iter().min().copied().unwrap_or(0),
+            indices.iter().max().copied().unwrap_or(0),
+            if self.encryption.is_some() { ".age" } else { "" }
+        )
+    }
+
+    // 打包计划: 待上传的压缩包, 待下载的目录和分块后的压缩包名称, 不下载, 压缩和上传
+    // 并发下载时完成顺序不定, 实际序号和分块可能与计划不同
+    pub fn plan(&self) -> DupPlan {
+        info!("use journal: {}", self.journal.path().display());
+        let mut plan = DupPlan { chunk_size: self.chunk_size, ..Default::default() };
+        plan.upload = self.journal.archives_in_stage(Stage::Archived).into_keys()
+            .map(|f| self.zip_path.join(f).display().to_string())
+            .collect();
+        if self.retention == Retention::Uploaded {
+            plan.clean = self.journal.archives_in_stage(Stage::Uploaded).into_values().flatten().collect();
+        }
+        // 已下载未压缩的目录排在前面, 新目录按顺序分配序号
+        let mut planned: Vec<(PathBuf, u64, bool)> = self.journal.in_stage(Stage::Downloaded).into_iter()
+            .map(|(name, r)| (self.folder_path(&name), r.index, true))
+            .filter(|(dir, _, _)| dir.is_dir())
+            .collect();
+        let next = self.journal.next_index();
+        for (i, post) in self.find_posts().into_iter().enumerate() {
+            planned.push((post, next + i as u64, false));
+        }
+        plan.dirs = planned.len();
+        for chunk in planned.chunks(self.chunk_size.max(1)) {
+            let indices: Vec<u64> = chunk.iter().map(|(_, i, _)| *i).collect();
+            let mut dirs = Vec::with_capacity(chunk.len());
+            for (dir, index, downloaded) in chunk {
+                let d = DirPlan {
+                    dir: self.folder_name(dir),
+                    index: *index,
+                    urls: Dup::url_count(dir),
+                    bytes: archive::dirs_size(std::slice::from_ref(dir)),
+                    downloaded: *downloaded,
+                };
+                plan.urls += d.urls;
+                plan.bytes += d.bytes;
+                dirs.push(d);
+            }
+            plan.archives.push(ArchivePlan { archive: self.zip_path.join(self.archive_name(&indices)).display().to_string(), dirs });
+        }
+        plan
+    }
+
+    // imgs 和 videos 中 info.txt 的下载地址数
+    fn url_count(post: &Path) -> usize {
+        ["imgs", "videos"].iter()
+            .filter_map(|sub| std::fs::read_to_string(post.join(sub).join("info.txt")).ok())
+            .map(|s| s.lines().filter(|l| !l.trim().is_empty()).count())
+            .sum()
+    }
+
+    // 并发下载待处理目录, 每满 chunk_size 个目录压缩, 校验并上传一次
+    // 磁盘使用率超过高水位时提前压缩上传, 回落到低水位以下后继续下载
+    pub fn start_download(&mut self, workers: usize, limiter: &RateLimiter) {
+        info!("use journal: {}", self.journal.path().display());
+        self.resume();
+        let posts = self.find_posts();
+        info!("{} dirs to download with {} workers", posts.len(), workers);
+
+        let (work_tx, work_rx) = mpsc::channel::<PathBuf>();
+        let (done_tx, done_rx) = mpsc::channel::<Done>();
+        let work_rx = Mutex::new(work_rx);
+        for post in posts {
+            let _ = work_tx.send(post);
+        }
+        drop(work_tx);
+
+        let guard = self.disk_guard.clone();
+        let events = self.events.clone();
+        std::thread::scope(|scope| {
+            // 下载线程
+            for _ in 0..workers.max(1) {
+                let done_tx = done_tx.clone();
+                let work_rx = &work_rx;
+                let guard = &guard;
+                let events = &events;
+                scope.spawn(move || loop {
+                    let post = match work_rx.lock().unwrap().recv() {
+                        Ok(post) => post,
+                        Err(_) => break
+                    };
+                    // 磁盘使用率过高, 通知提前压缩上传, 等待空间回收
+                    if let Some((path, usage)) = guard.over_high() {
+                        warn!("disk usage of {} is {:.1}%, pause downloading", path.display(), usage);
+                        let (reply_tx, reply_rx) = mpsc::channel();
+                        if done_tx.send(Done::DiskFull(reply_tx)).is_err() || reply_rx.recv().is_err() {
+                            break;
+                        }
+                        info!("disk space reclaimed, resume downloading");
+                    }
+                    limiter.acquire();
+                    let ok = Dup::download_post(&post, events);
+                    if done_tx.send(Done::Post(post, ok)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(done_tx);
+
+            // 当前线程负责压缩与上传
+            for done in done_rx {
+                let (post, ok) = match done {
+                    Done::Post(post, ok) => (post, ok),
+                    Done::DiskFull(reply) => {
+                        if !self.downloaded_vec.is_empty() {
+                            info!("disk usage is high, archive {} dirs early", self.downloaded_vec.len());
+                            self.compress_downloaded();
+                        }
+                        self.wait_for_disk();
+                        let _ = reply.send(());
+                        continue;
+                    }
+                };
+                let name = self.folder_name(&post);
+                if !ok {
+                    warn!("download {} failed", post.display());
+                    self.events.emit(Event::PostFailed {
+                        listing: Dup::tag_of(&self.root_dir),
+                        url: Dup::post_url(&post),
+                        dir: Some(post.display().to_string()),
+                        error: "download failed".to_string(),
+                    });
+                    continue;
+                }
+                self.events.emit(Event::PostCompleted {
+                    url: Dup::post_url(&post),
+                    dir: post.display().to_string(),
+                    files: Dup::url_count(&post),
+                    bytes: archive::dirs_size(std::slice::from_ref(&post)),
+                });
+                if let Err(e) = self.journal.downloaded(&name) {
+                    error!("write journal error: {}", e);
+                    continue;
+                }
+                self.downloaded_vec.push(post);
+                if self.downloaded_vec.len() >= self.chunk_size {
+                    self.compress_downloaded();
+                }
+            }
+        });
+        if !self.downloaded_vec.is_empty() {
+            self.compress_downloaded();
+        }
+    }
+
+    // 提前压缩上传后仍未回落到低水位时等待, 间隔逐渐加长
+    // 上传失败时源目录保留, 需要外部回收空间
+    fn wait_for_disk(&self) {
+        let mut delay = DISK_WAIT_MIN;
+        while !self.disk_guard.below_low() {
+            if let Some(store) = &self.store {
+                if store.prune().0 > 0 {
+                    continue;
+                }
+            }
+            if let Some((path, usage)) = self.disk_guard.over_high() {
+                warn!("disk usage of {} is still {:.1}% after archiving, check again in {}s", path.display(), usage, delay.as_secs());
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(DISK_WAIT_MAX);
+        }
+    }
+
+    // 查找待下载的帖子目录, 跳过任务日志中已有的
+    fn find_posts(&self) -> Vec<PathBuf> {
+        let mut posts: Vec<PathBuf> = Vec::new();
+        for entry in WalkDir::new(&self.root_dir)
+                .into_iter() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue
+            };
+            if entry.file_name() != "info.txt" {
+                continue;
+            }
+            let entry = entry.into_path();
+            info!("find info.txt in : {}", entry.to_str().unwrap());
+            // 二级父目录即帖子目录, imgs 与 videos 一起处理
+            let post = match entry.parent().and_then(|p| p.parent()) {
+                Some(p) if p.is_dir() => p.to_path_buf(),
+                _ => continue
+            };
+            if posts.contains(&post) {
+                continue;
+            }
+            let name = self.folder_name(&post);
+            if let Some(r) = self.journal.get(&name) {
+                info!("{} already {:?}, skip", &name, r.stage);
+                self.events.emit(Event::PostSkipped {
+                    listing: Dup::tag_of(&self.root_dir),
+                    url: Dup::post_url(&post),
+                    rule: "journal".to_string(),
+                    detail: format!("{} already {:?}", &name, r.stage),
+                });
+                continue;
+            }
+            posts.push(post);
+        }
+        posts
+    }
+
+    // 继续上次中断的任务
+    fn resume(&mut self) {
+        // 已压缩未上传
+        for (filename, names) in self.journal.archives_in_stage(Stage::Archived) {
+            let archive_path = self.zip_path.join(&filename);
+            let dirs: Vec<PathBuf> = names.iter().map(|n| self.folder_path(n)).collect();
+            // 只有公钥时无法解密, 压缩包已在写入时校验, 按 SHA256SUMS 确认没有变化
+            let verified = match &self.encryption {
+                Some(enc) if !enc.can_decrypt() => manifest::check_sums(&archive_path).map(|_| None),
+                enc => archive::verify(&archive_path, &self.root_dir, &dirs, enc.as_ref()).map(Some),
+            };
+            match verified {
+                Ok(entries) => {
+                    if let Some(entries) = entries.filter(|_| !manifest::manifest_path(&archive_path).exists()) {
+                        if let Err(e) = self.write_manifest(&archive_path, &names, &entries) {
+                            error!("write manifest for {} error: {}", archive_path.display(), e);
+                            continue;
+                        }
+                    }
+                    info!("resume upload {}", archive_path.display());
+                    self.finish_archive(&archive_path, &names);
+                },
+                Err(e) => {
+                    warn!("resume {} verify error: {}, archive again", archive_path.display(), e);
+                    let _ = std::fs::remove_file(&archive_path);
+                    if let Err(e) = self.journal.set_stage(&names, Stage::Downloaded, None) {
+                        error!("write journal error: {}", e);
+                    }
+                }
+            }
+        }
+        // 已上传未清理
+        if self.retention == Retention::Uploaded {
+            for (_, names) in self.journal.archives_in_stage(Stage::Uploaded) {
+                self.clean(&names);
+            }
+        }
+        // 已下载未压缩
+        for (name, _) in self.journal.in_stage(Stage::Downloaded) {
+            let dir = self.folder_path(&name);
+            if dir.is_dir() {
+                self.downloaded_vec.push(dir);
+            } else {
+                warn!("{} in journal but not exists", dir.display());
+            }
+        }
+        while self.downloaded_vec.len() >= self.chunk_size {
+            let rest = self.downloaded_vec.split_off(self.chunk_size);
+            self.compress_downloaded();
+            self.downloaded_vec = rest;
+        }
+    }
+
+    // 下载帖子目录下所有 info.txt, meta.json 记录了文件名时只补全缺失的文件
+    // 逐个文件调用 wget, 每个文件发出下载事件
+    fn download_post(post: &Path, events: &Events) -> bool {
+        let files = Dup::meta_files(post);
+        let mut result = true;
+        for sub in ["imgs", "videos"] {
+            let dir = post.join(sub);
+            if !dir.join("info.txt").exists() {
+                continue;
+            }
+            result = Dup::download_missing(&dir, files.as_deref().unwrap_or_default(), events) && result;
+        }
+        result
+    }
+
+    // cospull 写入 meta.json 的帖子地址, 没有 meta.json 时为空
+    fn post_url(post: &Path) -> String {
+        std::fs::read(post.join("meta.json")).ok()
+            .and_then(|d| serde_json::from_slice::<PostFiles>(&d).ok())
+            .map(|m| m.url)
+            .unwrap_or_default()
+    }
+
+    // cospull 写入 meta.json 的文件列表
+    fn meta_files(post: &Path) -> Option<Vec<MetaFile>> {
+        let data = std::fs::read(post.join("meta.json")).ok()?;
+        let meta: PostFiles = serde_json::from_slice(&data).ok()?;
+        if meta.files.is_empty() { None } else { Some(meta.files) }
+    }
+
+    // 按 meta.json 中的文件名下载缺失文件, 未记录的 URL 按原文件名下载
+    fn download_missing(dir: &Path, files: &[MetaFile], events: &Events) -> bool {
+        let urls = match std::fs::read_to_string(dir.join("info.txt")) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("read {} error: {}", dir.join("info.txt").display(), e);
+                return false;
+            }
+        };
+        let mut result = true;
+        for url in urls.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let name = files.iter()
+                .find(|f| f.url == url)
+                .and_then(|f| Path::new(&f.file).file_name().map(|n| n.to_os_string()));
+            if name.as_ref().is_some_and(|n| dir.join(n).exists()) {
+                continue;
+            }
+            let start = std::time::Instant::now();
+            events.emit(Event::FileStarted { url: url.to_string(), dir: dir.display().to_string() });
+            let args: Vec<&std::ffi::OsStr> = match &name {
+                Some(name) => vec!["-c".as_ref(), "-O".as_ref(), name.as_os_str(), url.as_ref()],
+                None => vec!["-nc".as_ref(), "-c".as_ref(), url.as_ref()],
+            };
+            // wget 只尝试一次, 由这里重试并发出重试事件, 报告中才能统计到
+            let mut attempt = 1;
+            let ok = loop {
+                if Dup::wget(dir, &args) {
+                    break true;
+                }
+                if attempt >= WGET_TRIES {
+                    break false;
+                }
+                warn!("wget {} failed, retry {}/{}", url, attempt, WGET_TRIES - 1);
+                events.emit(Event::FileRetry { url: url.to_string(), dir: dir.display().to_string(), attempt, error: "wget failed".to_string() });
+                std::thread::sleep(Duration::from_secs(2 * attempt as u64));
+                attempt += 1;
+            };
+            // 未记录文件名时按 URL 最后一段推断
+            let file = match &name {
+                Some(name) => dir.join(name),
+                None => dir.join(url.rsplit('/').next().unwrap_or_default()),
+            };
+            if ok {
+                let bytes = std::fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+                events.emit(Event::FileCompleted { url: url.to_string(), dir: dir.display().to_string(), file: file.display().to_string(), bytes, duration_ms: event::elapsed_ms(start) });
+            } else {
+                events.emit(Event::FileFailed { url: url.to_string(), dir: dir.display().to_string(), error: "wget failed".to_string(), duration_ms: event::elapsed_ms(start) });
+            }
+            result = ok && result;
+        }
+        result
+    }
+
+    fn wget(dir: &Path, args: &[&std::ffi::OsStr]) -> bool {
+        info!("start download files in dir: {}", dir.display());
+        match Command::new("wget")
+            .current_dir(dir)
+            .args(["-t", "1", "-T", "120"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status() {
+                Ok(status) => status.success(),
+                Err(e) => {
+                    error!("run wget in {} error: {}", dir.display(), e);
+                    false
+                }
+            }
+    }
+
+    fn compress_downloaded(&mut self) -> bool {
+        let dirs = std::mem::take(&mut self.downloaded_vec);
+        let names: Vec<String> = dirs.iter().map(|d| self.folder_name(d)).collect();
+        // 压缩包名称取首尾目录序号, 序号持久化在任务日志中, 重启后不会重复
+        let indices: Vec<u64> = names.iter()
+            .filter_map(|n| self.journal.get(n).map(|r| r.index))
+            .collect();
+        let filename = self.archive_name(&indices);
+        let archive_path = self.zip_path.join(&filename);
+        // 压缩包不会比源文件大很多, 空间不足时保留目录等待下次压缩
+        let need = archive::dirs_size(&dirs);
+        if let Ok(available) = disk::available(&self.zip_path) {
+            if available < need {
+                error!("not enough space in {} to compress {}: need {} bytes, available {} bytes",
+                    self.zip_path.display(), &filename, need, available);
+                self.downloaded_vec = dirs;
+                return false;
+            }
+        }
+        info!("start compress files in dir: {}", archive_path.to_str().unwrap());
+        let written = match archive::create(&archive_path, &self.root_dir, &dirs, self.encryption.as_ref()) {
+            Ok(written) => written,
+            Err(e) => {
+                error!("compress {} error: {}", archive_path.display(), e);
+                warn!("compress file error!");
+                let _ = std::fs::remove_file(&archive_path);
+                return false;
+            }
+        };
+        // 重新读取压缩包, 只有公钥时校验写入的明文流, 校验通过才允许删除源目录
+        let verified = match &self.encryption {
+            Some(enc) if !enc.can_decrypt() => archive::verify_entries(&written, &self.root_dir, &dirs),
+            enc => archive::verify(&archive_path, &self.root_dir, &dirs, enc.as_ref()),
+        };
+        let entries = match verified {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("verify {} error: {}, keep src files", archive_path.display(), e);
+                let _ = std::fs::remove_file(&archive_path);
+                return false;
+            }
+        };
+        info!("verify {} success", archive_path.display());
+        self.events.emit(Event::ArchiveCreated {
+            archive: archive_path.display().to_string(),
+            dirs: dirs.len(),
+            bytes: std::fs::metadata(&archive_path).map(|m| m.len()).unwrap_or(0),
+        });
+        if let Err(e) = self.write_manifest(&archive_path, &names, &entries) {
+            error!("write manifest for {} error: {}", archive_path.display(), e);
+            return false;
+        }
+        if let Err(e) = self.journal.set_stage(&names, Stage::Archived, Some(&filename)) {
+            error!("write journal error: {}", e);
+            return false;
+        }
+        self.finish_archive(&archive_path, &names);
+        true
+    }
+
+    // 生成清单和 SHA256SUMS
+    fn write_manifest(&self, archive_path: &Path, names: &[String], entries: &BTreeMap<String, archive::Entry>) -> anyhow::Result<()> {
+        let folders: Vec<(String, PathBuf)> = names.iter()
+            .map(|n| (n.clone(), self.folder_path(n)))
+            .collect();
+        let m = manifest::build(archive_path, &folders, entries);
+        for p in manifest::write_sidecars(archive_path, &m)? {
+            info!("write {}", p.display());
+        }
+        Ok(())
+    }
+
+    // 上传已校验的压缩包及清单, 按保留策略清理源目录
+    fn finish_archive(&mut self, archive_path: &Path, names: &[String]) {
+        let sidecars = [manifest::manifest_path(archive_path), manifest::sums_path(archive_path)];
+        for p in sidecars.iter().chain(std::iter::once(&archive_path.to_path_buf())) {
+            let ok = self.upload(p.clone());
+            self.events.emit(Event::UploadDone { file: p.display().to_string(), ok });
+            if !ok {
+                error!("upload file: {} failed, keep src files", p.to_str().unwrap());
+                return;
+            }
+        }
+        info!("upload file: {} success!", archive_path.to_str().unwrap());
+        if let Err(e) = self.journal.set_stage(names, Stage::Uploaded, None) {
+            error!("write journal error: {}", e);
+            return;
+        }
+        if self.retention == Retention::Uploaded {
+            self.clean(names);
+        }
+    }
+
+    fn clean(&mut self, names: &[String]) {
+        info!("archive verified and uploaded, rm src files");
+        let mut cleaned = Vec::with_capacity(names.len());
+        for name in names {
+            let dir = self.folder_path(name);
+            match std::fs::remove_dir_all(&dir) {
+                Ok(_) => cleaned.push(name.clone()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => cleaned.push(name.clone()),
+                Err(e) => error!("rm dir {} error: {}", dir.display(), e)
+            }
+        }
+        if let Err(e) = self.journal.set_stage(&cleaned, Stage::Cleaned, None) {
+            error!("write journal error: {}", e);
+        }
+        // 源目录中的文件硬链接到存储, 删除目录后数据仍占用空间
+        if let Some(store) = &self.store {
+            store.prune();
+        }
+    }
+
+    fn upload(&self, path: PathBuf) -> bool {
+        // use https://github.com/aoaostar/alidrive-uploader
+        let cmd = format!("alidrive -c ./alidrive.yaml {} CosJun/zips", 
+            path.to_str().unwrap()
+        );
+        info!("upload file use: {}", &cmd);
+        // info!("run command: {}", &cmd);
+        match Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&cmd)
+            .stdout(Stdio::null())
+            .spawn() {
+                Ok(mut child) => {
+                    match child.wait() {
+                        Ok(status) => status.success(),
+                        Err(_) => false
+                    }
+                },
+                Err(e) => {
+                    error!("run command {} error: {}", &cmd, e.to_string());
+                    false
+                }
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_releases_deduped_blobs() {
+        let tmp = std::env::temp_dir().join(format!("cosjun-dup-clean-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let (out, zips) = (tmp.join("out"), tmp.join("zips"));
+        let root = out.join("tag");
+        let store = Store::new(&out);
+        let names = vec!["tag/a".to_string(), "tag/b".to_string()];
+        let dirs: Vec<PathBuf> = names.iter().map(|n| out.join(n)).collect();
+        for dir in &dirs {
+            std::fs::create_dir_all(dir.join("imgs")).unwrap();
+            std::fs::write(dir.join("imgs").join("001_a.jpg"), b"same image").unwrap();
+            store.place(&dir.join("imgs").join("001_a.jpg")).unwrap();
+        }
+        let blobs = || WalkDir::new(out.join(crate::store::STORE_DIR)).into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .count();
+        assert_eq!(blobs(), 1);
+
+        let mut dup = Dup::builder(&root, &zips).disk_water(0.0, 0.0).build().unwrap();
+        for name in &names {
+            dup.journal.downloaded(name).unwrap();
+        }
+        let archive_path = zips.join(dup.archive_name(&[0, 1]));
+        archive::create(&archive_path, &root, &dirs, None).unwrap();
+        archive::verify(&archive_path, &root, &dirs, None).unwrap();
+        dup.journal.set_stage(&names, Stage::Uploaded, None).unwrap();
+
+        // 另一个目录仍引用相同内容, 数据保留
+        dup.clean(&names[..1]);
+        assert!(!dirs[0].exists());
+        assert_eq!(blobs(), 1);
+        dup.clean(&names[1..]);
+        assert_eq!(blobs(), 0);
+        assert_eq!(dup.journal.in_stage(Stage::Cleaned).len(), 2);
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+
+    #[test]
+    fn failed_verify_keeps_sources_and_removes_archive() {
+        let tmp = std::env::temp_dir().join(format!("cosjun-dup-verify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let (root, zips) = (tmp.join("out").join("tag"), tmp.join("zips"));
+        let post = root.join("a");
+        std::fs::create_dir_all(post.join("imgs")).unwrap();
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"image").unwrap();
+
+        let mut dup = Dup::builder(&root, &zips).disk_water(0.0, 0.0).build().unwrap();
+        let names = vec!["tag/a".to_string()];
+        dup.journal.downloaded(&names[0]).unwrap();
+        let filename = dup.archive_name(&[0]);
+        let archive_path = zips.join(&filename);
+        archive::create(&archive_path, &root, std::slice::from_ref(&post), None).unwrap();
+        dup.journal.set_stage(&names, Stage::Archived, Some(&filename)).unwrap();
+        // 压缩后源文件变化, 压缩包与源目录不一致
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"changed").unwrap();
+
+        dup.resume();
+        assert!(!archive_path.exists());
+        assert!(post.join("imgs").join("001_a.jpg").exists());
+        let record = dup.journal.get(&names[0]).unwrap();
+        assert_eq!((record.stage, record.archive.as_deref()), (Stage::Downloaded, None));
+        assert_eq!(dup.downloaded_vec, [post]);
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+}
@@ -0,0 +1,232 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use super::crypt::Encryption;
+use crate::store::{hash_file, hash_reader};
+
+// 压缩包内单个文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry
+{
+    pub size: u64,
+    pub sha256: String,
+}
+
+// 目录在压缩包内的名称: 相对下载根目录的上一级, 即 <tag>/<title>
+pub fn entry_name(root_dir: &Path, dir: &Path) -> PathBuf {
+    let base = root_dir.parent().unwrap_or(root_dir);
+    match dir.strip_prefix(base) {
+        Ok(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from(dir.file_name().unwrap_or(dir.as_os_str())),
+    }
+}
+
+// 目录内文件总大小
+pub fn dirs_size(dirs: &[PathBuf]) -> u64 {
+    dirs.iter()
+        .flat_map(|d| WalkDir::new(d).into_iter().filter_map(|e| e.ok()))
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+// 将多个目录打包为 tar.gz, 指定 enc 时在文件流上加密
+// 写入的明文流同时交给读取线程解析, 返回压缩包内的文件列表, 只有公钥时无法重新读取压缩包
+pub fn create(archive: &Path, root_dir: &Path, dirs: &[PathBuf], enc: Option<&Encryption>) -> anyhow::Result<BTreeMap<String, Entry>> {
+    let (tx, rx) = mpsc::sync_channel(64);
+    let reader = std::thread::spawn(move || entries_of(ChannelReader { rx, buf: Vec::new(), pos: 0 }));
+    let file = BufWriter::new(File::create(archive)?);
+    let mut file = match enc {
+        Some(enc) => write_tar(Tee { inner: enc.wrap(file)?, tx }, root_dir, dirs)?.inner.finish()?,
+        None => write_tar(Tee { inner: file, tx }, root_dir, dirs)?.inner,
+    };
+    file.flush()?;
+    reader.join().map_err(|_| anyhow::anyhow!("read archive stream of {} panicked", archive.display()))?
+}
+
+// 写入 inner 的数据同时发送给读取线程
+struct Tee<W>
+{
+    inner: W,
+    tx: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        // 读取线程出错退出后不再发送, 错误在 join 时返回
+        let _ = self.tx.send(buf[..n].to_vec());
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ChannelReader
+{
+    rx: mpsc::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.rx.recv() {
+                Ok(buf) => (self.buf, self.pos) = (buf, 0),
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn write_tar<W: Write>(output: W, root_dir: &Path, dirs: &[PathBuf]) -> anyhow::Result<W> {
+    let mut builder = tar::Builder::new(GzEncoder::new(output, Compression::default()));
+    builder.follow_symlinks(false);
+    for dir in dirs {
+        builder.append_dir_all(entry_name(root_dir, dir), dir)?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+// 读取压缩包, 返回 文件名 -> 文件信息
+pub fn read_entries(archive: &Path, enc: Option<&Encryption>) -> anyhow::Result<BTreeMap<String, Entry>> {
+    let file = BufReader::new(File::open(archive)?);
+    let input: Box<dyn Read> = match enc {
+        Some(enc) => enc.open(file)?,
+        None => Box::new(file),
+    };
+    entries_of(input)
+}
+
+// 解析 tar.gz 流中的文件
+fn entries_of<R: Read>(input: R) -> anyhow::Result<BTreeMap<String, Entry>> {
+    let mut ar = tar::Archive::new(GzDecoder::new(input));
+    let mut entries = BTreeMap::new();
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let size = entry.header().size()?;
+        let sha256 = hash_reader(&mut entry)?;
+        entries.insert(name, Entry { size, sha256 });
+    }
+    Ok(entries)
+}
+
+// 计算源目录内 文件名 -> 文件信息, 文件名与压缩包内一致
+pub fn dir_entries(root_dir: &Path, dirs: &[PathBuf]) -> anyhow::Result<BTreeMap<String, Entry>> {
+    let mut entries = BTreeMap::new();
+    for dir in dirs {
+        let prefix = entry_name(root_dir, dir);
+        for entry in WalkDir::new(dir) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry.path().strip_prefix(dir)?;
+            let name = prefix.join(rel).to_string_lossy().to_string();
+            let size = entry.metadata()?.len();
+            entries.insert(name, Entry { size, sha256: hash_file(entry.path())? });
+        }
+    }
+    Ok(entries)
+}
+
+// 重新读取压缩包, 校验文件列表和每个文件的 sha256 与源目录一致, 返回校验过的文件列表
+pub fn verify(archive: &Path, root_dir: &Path, dirs: &[PathBuf], enc: Option<&Encryption>) -> anyhow::Result<BTreeMap<String, Entry>> {
+    verify_entries(&read_entries(archive, enc)?, root_dir, dirs)
+}
+
+// 校验 create 时从明文流读取的文件列表与源目录一致
+pub fn verify_entries(packed: &BTreeMap<String, Entry>, root_dir: &Path, dirs: &[PathBuf]) -> anyhow::Result<BTreeMap<String, Entry>> {
+    let source = dir_entries(root_dir, dirs)?;
+    compare(packed, &source)?;
+    Ok(source)
+}
+
+// 比较压缩包内文件与期望的文件列表
+pub fn compare(packed: &BTreeMap<String, Entry>, expected: &BTreeMap<String, Entry>) -> anyhow::Result<()> {
+    for (name, entry) in expected {
+        match packed.get(name) {
+            Some(e) if e == entry => {}
+            Some(_) => anyhow::bail!("{} hash mismatch", name),
+            None => anyhow::bail!("{} missing in archive", name),
+        }
+    }
+    if let Some(name) = packed.keys().find(|k| !expected.contains_key(*k)) {
+        anyhow::bail!("{} in archive but not expected", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_detects_modified_files() {
+        let tmp = std::env::temp_dir().join(format!("cosjun-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let root = tmp.join("download");
+        let post = root.join("tag").join("title");
+        std::fs::create_dir_all(post.join("imgs")).unwrap();
+        std::fs::write(post.join("meta.json"), b"{}").unwrap();
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"image data").unwrap();
+        let dirs = vec![post.clone()];
+        let archive = tmp.join("0000.tar.gz");
+
+        create(&archive, &root, &dirs, None).unwrap();
+        let entries = verify(&archive, &root, &dirs, None).unwrap();
+        let names: Vec<_> = entries.keys().map(String::as_str).collect();
+        assert_eq!(names, ["download/tag/title/imgs/001_a.jpg", "download/tag/title/meta.json"]);
+        assert_eq!(entries["download/tag/title/imgs/001_a.jpg"].size, 10);
+
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"image datb").unwrap();
+        let err = verify(&archive, &root, &dirs, None).unwrap_err();
+        assert_eq!(err.to_string(), "download/tag/title/imgs/001_a.jpg hash mismatch");
+
+        std::fs::write(post.join("extra.txt"), b"x").unwrap();
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"image data").unwrap();
+        let err = verify(&archive, &root, &dirs, None).unwrap_err();
+        assert_eq!(err.to_string(), "download/tag/title/extra.txt missing in archive");
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+
+    #[test]
+    fn create_returns_entries_without_identity() {
+        let tmp = std::env::temp_dir().join(format!("cosjun-archive-age-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let root = tmp.join("download");
+        let post = root.join("tag").join("title");
+        std::fs::create_dir_all(&post).unwrap();
+        std::fs::write(post.join("001_a.jpg"), vec![7u8; 200_000]).unwrap();
+        let dirs = vec![post];
+        let archive = tmp.join("0000.tar.gz.age");
+
+        let identity = age::x25519::Identity::generate();
+        let enc = Encryption::recipients(&[identity.to_public().to_string()], None).unwrap();
+        let written = create(&archive, &root, &dirs, Some(&enc)).unwrap();
+        assert_eq!(verify_entries(&written, &root, &dirs).unwrap(), written);
+        assert_eq!(written["download/tag/title/001_a.jpg"].size, 200_000);
+
+        let dec = Encryption::Recipients { recipients: Vec::new(), identities: vec![identity] };
+        assert_eq!(read_entries(&archive, Some(&dec)).unwrap(), written);
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+}
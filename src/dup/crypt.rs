@@ -0,0 +1,189 @@
+use age::secrecy::{Secret, SecretString};
+use age::stream::StreamWriter;
+use age::x25519;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+// 口令所在环境变量
+pub const PASSPHRASE_ENV: &str = "COSDUP_PASSPHRASE";
+
+// 压缩包加密方式
+pub enum Encryption
+{
+    // 口令加密
+    Passphrase(SecretString),
+    // 公钥加密, 私钥仅用于校验和解密, 没有私钥时在写入时校验
+    Recipients {
+        recipients: Vec<x25519::Recipient>,
+        identities: Vec<x25519::Identity>,
+    },
+}
+
+impl Encryption {
+    pub fn passphrase_from_env() -> anyhow::Result<Self> {
+        match std::env::var(PASSPHRASE_ENV) {
+            Ok(p) if !p.is_empty() => Ok(Encryption::Passphrase(Secret::new(p))),
+            _ => anyhow::bail!("env {} is empty", PASSPHRASE_ENV),
+        }
+    }
+
+    pub fn recipients(recipients: &[String], identity_file: Option<&Path>) -> anyhow::Result<Self> {
+        let recipients = recipients.iter()
+            .map(|r| r.parse::<x25519::Recipient>().map_err(|e| anyhow::anyhow!("bad recipient {}: {}", r, e)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let identities = match identity_file {
+            Some(p) => load_identities(p)?,
+            None => Vec::new(),
+        };
+        Ok(Encryption::Recipients { recipients, identities })
+    }
+
+    // 读取 age 格式的公钥文件, 每行一个公钥, # 开头为注释
+    pub fn read_recipients(path: &Path) -> anyhow::Result<Vec<String>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("{}", e).context(format!("read recipients {} error", path.display())))?;
+        Ok(text.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    // 只有私钥的解密配置
+    pub fn identities(identity_file: &Path) -> anyhow::Result<Self> {
+        Ok(Encryption::Recipients { recipients: Vec::new(), identities: load_identities(identity_file)? })
+    }
+
+    // 能否重新读取加密后的压缩包
+    pub fn can_decrypt(&self) -> bool {
+        match self {
+            Encryption::Passphrase(_) => true,
+            Encryption::Recipients { identities, .. } => !identities.is_empty(),
+        }
+    }
+
+    // 在输出流上叠加加密层, 写完后需调用 finish
+    pub fn wrap<W: Write>(&self, output: W) -> anyhow::Result<StreamWriter<W>> {
+        let encryptor = match self {
+            Encryption::Passphrase(p) => age::Encryptor::with_user_passphrase(p.clone()),
+            Encryption::Recipients { recipients, .. } => {
+                let recipients = recipients.iter()
+                    .map(|r| Box::new(r.clone()) as Box<dyn age::Recipient + Send>)
+                    .collect();
+                age::Encryptor::with_recipients(recipients)
+                    .ok_or_else(|| anyhow::anyhow!("no recipients"))?
+            }
+        };
+        Ok(encryptor.wrap_output(output)?)
+    }
+
+    // 解密输入流
+    pub fn open<'a, R: Read + 'a>(&self, input: R) -> anyhow::Result<Box<dyn Read + 'a>> {
+        match (age::Decryptor::new(BufReader::new(input))?, self) {
+            (age::Decryptor::Passphrase(d), Encryption::Passphrase(p)) => {
+                Ok(Box::new(d.decrypt(p, None)?))
+            },
+            (age::Decryptor::Recipients(d), Encryption::Recipients { identities, .. }) => {
+                let reader = d.decrypt(identities.iter().map(|i| i as &dyn age::Identity))?;
+                Ok(Box::new(reader))
+            },
+            (age::Decryptor::Passphrase(_), _) => anyhow::bail!("archive is encrypted with a passphrase, set {}", PASSPHRASE_ENV),
+            (age::Decryptor::Recipients(_), _) => anyhow::bail!("archive is encrypted to recipients, use --identity"),
+        }
+    }
+}
+
+fn load_identities(path: &Path) -> anyhow::Result<Vec<x25519::Identity>> {
+    let file = age::IdentityFile::from_file(path.to_string_lossy().to_string())
+        .map_err(|e| anyhow::anyhow!("{}", e).context(format!("read identity {} error", path.display())))?;
+    let identities: Vec<x25519::Identity> = file.into_identities().into_iter()
+        .map(|e| match e {
+            age::IdentityFileEntry::Native(i) => i,
+        })
+        .collect();
+    if identities.is_empty() {
+        anyhow::bail!("no identity in {}", path.display());
+    }
+    Ok(identities)
+}
+
+// 是否为加密的压缩包
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension().map(|e| e == "age").unwrap_or(false)
+}
+
+// 解密压缩包到 output
+pub fn decrypt_file(input: &Path, output: &Path, enc: &Encryption) -> anyhow::Result<u64> {
+    let file = std::fs::File::open(input)?;
+    let mut reader = enc.open(file)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let result = std::io::copy(&mut reader, &mut out).and_then(|n| out.flush().map(|_| n));
+    if result.is_err() {
+        // 解密失败不保留不完整的输出
+        drop(out);
+        let _ = std::fs::remove_file(output);
+    }
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("cosjun-crypt-{}-{}", std::process::id(), name))
+    }
+
+    fn encrypt(enc: &Encryption, path: &Path, data: &[u8]) {
+        let mut w = enc.wrap(std::fs::File::create(path).unwrap()).unwrap();
+        w.write_all(data).unwrap();
+        w.finish().unwrap();
+    }
+
+    #[test]
+    fn recipients_round_trip() {
+        let identity = x25519::Identity::generate();
+        let (id_file, keys) = (temp_path("identity.txt"), temp_path("recipients.txt"));
+        std::fs::write(&id_file, format!("# key\n{}\n", identity.to_string().expose_secret())).unwrap();
+        std::fs::write(&keys, format!("# backup\n\n{}\n", identity.to_public())).unwrap();
+
+        let recipients = Encryption::read_recipients(&keys).unwrap();
+        assert_eq!(recipients, [identity.to_public().to_string()]);
+        let enc = Encryption::recipients(&recipients, None).unwrap();
+        assert!(!enc.can_decrypt());
+        let (archive, out) = (temp_path("a.tar.gz.age"), temp_path("a.tar.gz"));
+        encrypt(&enc, &archive, b"archive data");
+        assert!(is_encrypted(&archive));
+        assert_ne!(std::fs::read(&archive).unwrap(), b"archive data");
+
+        let dec = Encryption::identities(&id_file).unwrap();
+        assert_eq!(decrypt_file(&archive, &out, &dec).unwrap(), 12);
+        assert_eq!(std::fs::read(&out).unwrap(), b"archive data");
+        std::fs::remove_file(&out).unwrap();
+
+        let other = Encryption::Recipients { recipients: Vec::new(), identities: vec![x25519::Identity::generate()] };
+        assert!(decrypt_file(&archive, &out, &other).is_err());
+        assert!(!out.exists());
+        let pass = Encryption::Passphrase(Secret::new("secret".to_string()));
+        assert_eq!(decrypt_file(&archive, &out, &pass).unwrap_err().to_string(), "archive is encrypted to recipients, use --identity");
+        for p in [id_file, keys, archive] {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+
+    #[test]
+    fn passphrase_round_trip() {
+        let enc = Encryption::Passphrase(Secret::new("secret".to_string()));
+        assert!(enc.can_decrypt());
+        let (archive, out) = (temp_path("p.tar.gz.age"), temp_path("p.tar.gz"));
+        encrypt(&enc, &archive, b"archive data");
+        assert_eq!(decrypt_file(&archive, &out, &enc).unwrap(), 12);
+        assert_eq!(std::fs::read(&out).unwrap(), b"archive data");
+        std::fs::remove_file(&out).unwrap();
+        let wrong = Encryption::Passphrase(Secret::new("wrong".to_string()));
+        assert!(decrypt_file(&archive, &out, &wrong).is_err());
+        assert!(!out.exists());
+        let _ = std::fs::remove_file(archive);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// 目录处理阶段, 按顺序推进
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Downloaded,
+    Archived,
+    Uploaded,
+    Cleaned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderRecord {
+    // 首次下载完成时分配的序号, 用于生成稳定的压缩包名称
+    pub index: u64,
+    pub stage: Stage,
+    // 所属压缩包名称
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+}
+
+// 持久化任务日志, 每次状态变化立即写盘, 中断后可继续
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    next_index: u64,
+    // 目录名(<tag>/<title>) -> 记录
+    folders: BTreeMap<String, FolderRecord>,
+}
+
+impl Journal {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self { path, next_index: 0, folders: BTreeMap::new() });
+        }
+        let data = std::fs::read(&path)?;
+        let mut journal: Journal = serde_json::from_slice(&data)
+            .map_err(|e| anyhow::anyhow!("{}", e).context(format!("parse journal {} error", path.display())))?;
+        journal.path = path;
+        Ok(journal)
+    }
+
+    // 先写临时文件再重命名, 避免写一半被中断
+    pub fn save(&self) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 下一个下载完成的目录将分配的序号
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FolderRecord> {
+        self.folders.get(name)
+    }
+
+    // 记录下载完成, 分配序号
+    pub fn downloaded(&mut self, name: &str) -> anyhow::Result<u64> {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.folders.insert(name.to_string(), FolderRecord { index, stage: Stage::Downloaded, archive: None });
+        self.save()?;
+        Ok(index)
+    }
+
+    pub fn set_stage(&mut self, names: &[String], stage: Stage, archive: Option<&str>) -> anyhow::Result<()> {
+        for name in names {
+            if let Some(r) = self.folders.get_mut(name) {
+                r.stage = stage;
+                if stage == Stage::Downloaded || archive.is_some() {
+                    r.archive = archive.map(|s| s.to_string());
+                }
+            }
+        }
+        self.save()
+    }
+
+    // 处于指定阶段的目录
+    pub fn in_stage(&self, stage: Stage) -> Vec<(String, FolderRecord)> {
+        let mut v: Vec<_> = self.folders.iter()
+            .filter(|(_, r)| r.stage == stage)
+            .map(|(k, r)| (k.clone(), r.clone()))
+            .collect();
+        v.sort_by_key(|(_, r)| r.index);
+        v
+    }
+
+    // 按压缩包分组处于指定阶段的目录
+    pub fn archives_in_stage(&self, stage: Stage) -> BTreeMap<String, Vec<String>> {
+        let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, r) in self.in_stage(stage) {
+            if let Some(a) = r.archive {
+                map.entry(a).or_default().push(name);
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_resume_stages() {
+        let path = std::env::temp_dir().join(format!("cosjun-journal-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut journal = Journal::load(path.clone()).unwrap();
+        assert_eq!(journal.next_index(), 0);
+        assert_eq!(journal.downloaded("tag/b").unwrap(), 0);
+        assert_eq!(journal.downloaded("tag/a").unwrap(), 1);
+        assert_eq!(journal.downloaded("tag/c").unwrap(), 2);
+        journal.set_stage(&["tag/b".to_string(), "tag/a".to_string()], Stage::Archived, Some("0000.tar.gz")).unwrap();
+
+        // 中断后重新加载, 序号和阶段保持不变
+        let mut journal = Journal::load(path.clone()).unwrap();
+        assert_eq!(journal.next_index(), 3);
+        let downloaded: Vec<_> = journal.in_stage(Stage::Downloaded).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(downloaded, ["tag/c"]);
+        let archives = journal.archives_in_stage(Stage::Archived);
+        assert_eq!(archives["0000.tar.gz"], ["tag/b", "tag/a"]);
+
+        journal.set_stage(&["tag/b".to_string()], Stage::Uploaded, None).unwrap();
+        let journal = Journal::load(path.clone()).unwrap();
+        let b = journal.get("tag/b").unwrap();
+        assert_eq!((b.index, b.stage, b.archive.as_deref()), (0, Stage::Uploaded, Some("0000.tar.gz")));
+        assert!(journal.get("tag/d").is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_reports_broken_journal() {
+        let path = std::env::temp_dir().join(format!("cosjun-journal-broken-{}.json", std::process::id()));
+        std::fs::write(&path, b"{not json").unwrap();
+        let err = Journal::load(path.clone()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with(&format!("parse journal {} error", path.display())));
+        let _ = std::fs::remove_file(&path);
+    }
+}
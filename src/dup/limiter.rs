@@ -0,0 +1,35 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 限速器: 按固定间隔放行, 多个线程共享
+pub struct RateLimiter
+{
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // 每分钟最多放行 per_minute 次, 0 表示不限速
+    pub fn per_minute(per_minute: u32) -> Self {
+        let interval = if per_minute == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(60) / per_minute
+        };
+        Self { interval, next: Mutex::new(Instant::now()) }
+    }
+
+    // 阻塞直到获得许可
+    pub fn acquire(&self) {
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + self.interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use super::archive::{self, Entry};
+use super::crypt::Encryption;
+use crate::store::hash_file;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileManifest
+{
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostManifest
+{
+    // 压缩包内目录名 <tag>/<title>
+    pub folder: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub tag: String,
+    pub files: Vec<FileManifest>,
+}
+
+// 压缩包清单, 与压缩包一起上传
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest
+{
+    pub archive: String,
+    // 创建时间, unix 秒
+    pub created: u64,
+    pub posts: Vec<PostManifest>,
+}
+
+// cospull 写入帖子目录的 meta.json, 只读取需要的字段
+#[derive(Debug, Default, Deserialize)]
+struct PostMeta
+{
+    title: Option<String>,
+    url: Option<String>,
+    tag: Option<String>,
+}
+
+pub fn manifest_path(archive: &Path) -> PathBuf {
+    sidecar(archive, "manifest.json")
+}
+
+pub fn sums_path(archive: &Path) -> PathBuf {
+    sidecar(archive, "SHA256SUMS")
+}
+
+fn sidecar(archive: &Path, ext: &str) -> PathBuf {
+    let mut name = archive.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    archive.with_file_name(name)
+}
+
+// 根据校验过的文件列表生成清单
+pub fn build(archive: &Path, folders: &[(String, PathBuf)], entries: &BTreeMap<String, Entry>) -> Manifest {
+    let posts = folders.iter().map(|(folder, dir)| {
+        let meta: PostMeta = std::fs::read(dir.join("meta.json")).ok()
+            .and_then(|d| serde_json::from_slice(&d).ok())
+            .unwrap_or_default();
+        let path = Path::new(folder);
+        let prefix = format!("{}/", folder);
+        PostManifest {
+            folder: folder.clone(),
+            title: meta.title.unwrap_or_else(|| name_of(path)),
+            url: meta.url,
+            tag: meta.tag.unwrap_or_else(|| path.parent().map(name_of).unwrap_or_default()),
+            files: entries.iter()
+                .filter(|(name, _)| name.starts_with(&prefix))
+                .map(|(name, e)| FileManifest { name: name.clone(), size: e.size, sha256: e.sha256.clone() })
+                .collect(),
+        }
+    }).collect();
+    Manifest {
+        archive: archive.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        posts,
+    }
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// 写入清单和 SHA256SUMS, 返回写入的文件
+pub fn write_sidecars(archive: &Path, manifest: &Manifest) -> anyhow::Result<Vec<PathBuf>> {
+    let mpath = manifest_path(archive);
+    std::fs::write(&mpath, serde_json::to_vec_pretty(manifest)?)?;
+    // 与 sha256sum -c 兼容
+    let mut sums = String::new();
+    for p in [archive, mpath.as_path()] {
+        sums += &format!("{}  {}\n", hash_file(p)?, name_of(p));
+    }
+    let spath = sums_path(archive);
+    std::fs::write(&spath, sums)?;
+    Ok(vec![mpath, spath])
+}
+
+// 根据清单和 SHA256SUMS 校验压缩包, 加密的压缩包需要 enc 解密
+pub fn verify(archive: &Path, enc: Option<&Encryption>) -> anyhow::Result<Manifest> {
+    check_sums(archive)?;
+    let mpath = manifest_path(archive);
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(&mpath)?)
+        .map_err(|e| anyhow::anyhow!("{}", e).context(format!("parse {} error", mpath.display())))?;
+    let expected: BTreeMap<String, Entry> = manifest.posts.iter()
+        .flat_map(|p| p.files.iter())
+        .map(|f| (f.name.clone(), Entry { size: f.size, sha256: f.sha256.clone() }))
+        .collect();
+    archive::compare(&archive::read_entries(archive, enc)?, &expected)?;
+    Ok(manifest)
+}
+
+// 按 SHA256SUMS 校验压缩包和清单文件, 不需要解密
+// 只接受压缩包和清单自身的文件名, 不读取其他路径
+pub fn check_sums(archive: &Path) -> anyhow::Result<()> {
+    let spath = sums_path(archive);
+    let sums = std::fs::read_to_string(&spath)
+        .map_err(|e| anyhow::anyhow!("{}", e).context(format!("read {} error", spath.display())))?;
+    let known = [archive.to_path_buf(), manifest_path(archive)];
+    let mut checked = false;
+    for line in sums.lines().filter(|l| !l.trim().is_empty()) {
+        let (hash, name) = line.split_once("  ")
+            .ok_or_else(|| anyhow::anyhow!("bad line in {}: {}", spath.display(), line))?;
+        let path = known.iter().find(|p| name_of(p) == name)
+            .ok_or_else(|| anyhow::anyhow!("unexpected file {} in {}", name, spath.display()))?;
+        if hash_file(path)? != hash {
+            anyhow::bail!("{} sha256 mismatch", name);
+        }
+        checked |= path == archive;
+    }
+    if !checked {
+        anyhow::bail!("{} not listed in {}", name_of(archive), spath.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture
+    {
+        tmp: PathBuf,
+        archive: PathBuf,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.tmp);
+        }
+    }
+
+    // 打包一个帖子目录并写入清单和 SHA256SUMS
+    fn fixture(name: &str) -> Fixture {
+        let tmp = std::env::temp_dir().join(format!("cosjun-manifest-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let root = tmp.join("download");
+        let post = root.join("tag").join("title");
+        std::fs::create_dir_all(post.join("imgs")).unwrap();
+        std::fs::write(post.join("imgs").join("001_a.jpg"), b"image").unwrap();
+        std::fs::write(post.join("meta.json"), br#"{"title":"A title","url":"https://a.com/1.html","tag":"cos"}"#).unwrap();
+        let zips = tmp.join("zips");
+        std::fs::create_dir_all(&zips).unwrap();
+        let archive = zips.join("cos_tag_0-0.tar.gz");
+        let dirs = vec![post.clone()];
+        archive::create(&archive, &root, &dirs, None).unwrap();
+        let entries = archive::verify(&archive, &root, &dirs, None).unwrap();
+        let manifest = build(&archive, &[("download/tag/title".to_string(), post)], &entries);
+        write_sidecars(&archive, &manifest).unwrap();
+        Fixture { tmp, archive }
+    }
+
+    #[test]
+    fn build_and_verify() {
+        let f = fixture("ok");
+        let m = verify(&f.archive, None).unwrap();
+        assert_eq!(m.archive, "cos_tag_0-0.tar.gz");
+        assert_eq!(m.posts.len(), 1);
+        let post = &m.posts[0];
+        assert_eq!((post.title.as_str(), post.tag.as_str(), post.url.as_deref()), ("A title", "cos", Some("https://a.com/1.html")));
+        let files: Vec<_> = post.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(files, ["download/tag/title/imgs/001_a.jpg", "download/tag/title/meta.json"]);
+        let sums = std::fs::read_to_string(sums_path(&f.archive)).unwrap();
+        assert!(sums.lines().any(|l| l.ends_with("  cos_tag_0-0.tar.gz")));
+        assert!(sums.lines().any(|l| l.ends_with("  cos_tag_0-0.tar.gz.manifest.json")));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_archive() {
+        let f = fixture("tampered");
+        let mut data = std::fs::read(&f.archive).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&f.archive, data).unwrap();
+        assert_eq!(verify(&f.archive, None).unwrap_err().to_string(), "cos_tag_0-0.tar.gz sha256 mismatch");
+    }
+
+    #[test]
+    fn check_sums_rejects_other_paths() {
+        let f = fixture("paths");
+        let spath = sums_path(&f.archive);
+        let sums = std::fs::read_to_string(&spath).unwrap();
+        for name in ["../download/tag/title/meta.json", "/etc/passwd"] {
+            std::fs::write(&spath, format!("{}{}  {}\n", sums, "0".repeat(64), name)).unwrap();
+            assert_eq!(check_sums(&f.archive).unwrap_err().to_string(), format!("unexpected file {} in {}", name, spath.display()));
+        }
+        let manifest_only: String = sums.lines().filter(|l| l.ends_with(".manifest.json")).map(|l| format!("{}\n", l)).collect();
+        std::fs::write(&spath, manifest_only).unwrap();
+        assert_eq!(check_sums(&f.archive).unwrap_err().to_string(), format!("cos_tag_0-0.tar.gz not listed in {}", spath.display()));
+    }
+}
@@ -0,0 +1,141 @@
+// 下载和打包过程中的结构化事件, 由前端决定显示进度条还是输出 NDJSON
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // 列表页已获取, posts 为页面中的帖子数
+    PageFetched { listing: String, page: i32, url: String, posts: usize },
+    // 获取或解析列表页失败, 继续下一页
+    PageFailed { listing: String, page: i32, url: String, error: String },
+    // 获取总页数失败, 不再翻页
+    ListingFailed { listing: String, error: String },
+    PostDiscovered { listing: String, url: String, title: String, date: Option<String> },
+    PostSkipped { listing: String, url: String, rule: String, detail: String },
+    // 列表获取完毕, 开始逐个处理 posts 个帖子, 每个帖子以 skipped, failed, completed 或 planned 结束
+    Queued { listing: String, posts: usize },
+    // dir 为已确定的帖子目录, url 未知时为空
+    PostFailed { listing: String, url: String, dir: Option<String>, error: String },
+    // 开始下载帖子中的文件
+    PostStarted { url: String, title: String, dir: String, images: usize, videos: usize },
+    PostCompleted { url: String, dir: String, files: usize, bytes: u64 },
+    // --dry-run 时帖子的计划文件数和估算字节数
+    PostPlanned { url: String, dir: String, files: usize, bytes: u64 },
+    // 文件事件都带有所在目录, 不同帖子可能包含相同 URL, 以 dir 和 url 区分
+    FileStarted { url: String, dir: String },
+    // 已下载的字节数, total 为响应的 Content-Length
+    FileProgress { url: String, dir: String, bytes: u64, total: Option<u64> },
+    // 下载出错, 等待后第 attempt 次重试
+    FileRetry { url: String, dir: String, attempt: usize, error: String },
+    FileCompleted { url: String, dir: String, file: String, bytes: u64, duration_ms: u64 },
+    FileFailed { url: String, dir: String, error: String, duration_ms: u64 },
+    ArchiveCreated { archive: String, dirs: usize, bytes: u64 },
+    UploadDone { file: String, ok: bool },
+    // 运行结束时每个标签的汇总, 整个运行的报告或下载计划
+    Summary(serde_json::Value),
+    Report(serde_json::Value),
+    Plan(serde_json::Value),
+}
+
+type Callback = dyn Fn(&Event) + Send + Sync;
+
+// 事件接收方, 默认丢弃所有事件
+#[derive(Clone, Default)]
+pub struct Events
+{
+    callback: Option<Arc<Callback>>,
+}
+
+impl Events {
+    pub fn new(callback: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Self { callback: Some(Arc::new(callback)) }
+    }
+
+    // 事件发送到通道, 由调用方在其他任务中接收
+    pub fn channel() -> (Self, tokio::sync::mpsc::UnboundedReceiver<Event>) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        (Self::new(move |e| { let _ = tx.send(e.clone()); }), rx)
+    }
+
+    // 每行一个 JSON 事件写到 stdout, 附带 unix 毫秒时间
+    // 下载进度事件过于频繁, 不写入
+    pub fn ndjson() -> Self {
+        Self::new(|e| {
+            if let Event::FileProgress { .. } = e {
+                return;
+            }
+            let mut value = match serde_json::to_value(e) {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            if let Some(map) = value.as_object_mut() {
+                let time = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0);
+                map.insert("time".to_string(), time.into());
+            }
+            let mut out = std::io::stdout().lock();
+            let _ = writeln!(out, "{}", value);
+            let _ = out.flush();
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(cb) = &self.callback {
+            cb(&event);
+        }
+    }
+}
+
+impl std::fmt::Debug for Events {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Events").field("enabled", &self.is_enabled()).finish()
+    }
+}
+
+// 单个文件的下载进度, 间隔 PROGRESS_INTERVAL 发出一次 FileProgress
+pub struct Meter<'a>
+{
+    events: &'a Events,
+    url: &'a str,
+    dir: String,
+    total: Option<u64>,
+    bytes: u64,
+    last: std::time::Instant,
+}
+
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+impl<'a> Meter<'a> {
+    pub fn new(events: &'a Events, url: &'a str, dir: &std::path::Path, total: Option<u64>) -> Self {
+        let meter = Self { events, url, dir: dir.display().to_string(), total, bytes: 0, last: std::time::Instant::now() };
+        meter.emit();
+        meter
+    }
+
+    pub fn add(&mut self, n: u64) {
+        self.bytes += n;
+        if self.last.elapsed() >= PROGRESS_INTERVAL {
+            self.last = std::time::Instant::now();
+            self.emit();
+        }
+    }
+
+    fn emit(&self) {
+        if self.events.is_enabled() {
+            self.events.emit(Event::FileProgress { url: self.url.to_string(), dir: self.dir.clone(), bytes: self.bytes, total: self.total });
+        }
+    }
+}
+
+// 毫秒耗时
+pub fn elapsed_ms(start: std::time::Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
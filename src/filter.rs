@@ -0,0 +1,250 @@
+use regex::Regex;
+use serde::Serialize;
+
+// 爬取范围和帖子筛选规则: 列表页码, 发布日期窗口, 标题, 图片数量和媒体类型
+#[derive(Debug, Clone)]
+pub struct Filter
+{
+    pub from_page: i32,
+    // -1 表示到最后一页
+    pub to_page: i32,
+    // YYYY-MM-DD, 包含当天
+    pub since: Option<String>,
+    pub until: Option<String>,
+    // 标题需匹配其中之一, 为空时不限制
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+    pub min_images: Option<usize>,
+    pub max_images: Option<usize>,
+    pub media: MediaKind,
+}
+
+// 下载的媒体类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaKind {
+    Images,
+    Videos,
+    Both,
+}
+
+impl MediaKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "images" => Some(Self::Images),
+            "videos" => Some(Self::Videos),
+            "both" => Some(Self::Both),
+            _ => None
+        }
+    }
+
+    pub fn images(&self) -> bool {
+        *self != Self::Videos
+    }
+
+    pub fn videos(&self) -> bool {
+        *self != Self::Images
+    }
+}
+
+// 帖子被跳过的规则和说明
+#[derive(Debug, Serialize)]
+pub struct Skip
+{
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+impl Skip {
+    fn new(rule: &'static str, detail: String) -> Self {
+        Self { rule, detail }
+    }
+}
+
+// 日期相对窗口的位置
+#[derive(Debug, PartialEq)]
+pub enum Window {
+    In,
+    // 晚于 until
+    Newer,
+    // 早于 since
+    Older,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            from_page: 1,
+            to_page: -1,
+            since: None,
+            until: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_images: None,
+            max_images: None,
+            media: MediaKind::Both,
+        }
+    }
+}
+
+impl Filter {
+    // 无法识别的日期视为在窗口内, 由详情页日期再次检查
+    pub fn window(&self, date: Option<&str>) -> Window {
+        let date = match date.and_then(parse_date) {
+            Some(d) => d,
+            None => return Window::In,
+        };
+        if self.since.as_ref().is_some_and(|s| date < *s) {
+            return Window::Older;
+        }
+        if self.until.as_ref().is_some_and(|u| date > *u) {
+            return Window::Newer;
+        }
+        Window::In
+    }
+
+    // 列表页中按标题筛选
+    pub fn check_title(&self, title: &str) -> Option<Skip> {
+        if !self.include.is_empty() && !self.include.iter().any(|r| r.is_match(title)) {
+            let patterns: Vec<&str> = self.include.iter().map(|r| r.as_str()).collect();
+            return Some(Skip::new("include", format!("title matches none of {:?}", patterns)));
+        }
+        if let Some(r) = self.exclude.iter().find(|r| r.is_match(title)) {
+            return Some(Skip::new("exclude", format!("title matches {}", r.as_str())));
+        }
+        None
+    }
+
+    pub fn check_date(&self, date: Option<&str>) -> Option<Skip> {
+        match self.window(date) {
+            Window::In => None,
+            Window::Newer => Some(Skip::new("until", format!("dated {} after {}", date.unwrap_or_default(), self.until.as_deref().unwrap_or_default()))),
+            Window::Older => Some(Skip::new("since", format!("dated {} before {}", date.unwrap_or_default(), self.since.as_deref().unwrap_or_default()))),
+        }
+    }
+
+    // 详情页中按图片数量和媒体类型筛选
+    pub fn check_media(&self, images: usize, videos: usize) -> Option<Skip> {
+        if let Some(min) = self.min_images.filter(|m| images < *m) {
+            return Some(Skip::new("min-images", format!("{} images, fewer than {}", images, min)));
+        }
+        if let Some(max) = self.max_images.filter(|m| images > *m) {
+            return Some(Skip::new("max-images", format!("{} images, more than {}", images, max)));
+        }
+        let wanted = (if self.media.images() { images } else { 0 }) + (if self.media.videos() { videos } else { 0 });
+        if wanted == 0 {
+            return Some(Skip::new("media", format!("{} images and {} videos, none of {:?}", images, videos, self.media)));
+        }
+        None
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} to {}", self.since.as_deref().unwrap_or("-"), self.until.as_deref().unwrap_or("-"))
+    }
+}
+
+// 取日期开头的 YYYY-MM-DD, 如 2023-01-05T12:00:00+08:00
+pub fn parse_date(s: &str) -> Option<String> {
+    let d: String = s.trim().chars().take(10).collect();
+    let valid = d.len() == 10 && d.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() });
+    if !valid {
+        return None;
+    }
+    let year: u32 = d[0..4].parse().ok()?;
+    let month: u32 = d[5..7].parse().ok()?;
+    let day: u32 = d[8..10].parse().ok()?;
+    if (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day) { Some(d) } else { None }
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(since: Option<&str>, until: Option<&str>) -> Filter {
+        Filter { since: since.map(|s| s.to_string()), until: until.map(|s| s.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn window_classifies_dates() {
+        let f = window(Some("2023-03-01"), Some("2023-03-31"));
+        assert_eq!(f.window(Some("2023-02-28T23:59:59+08:00")), Window::Older);
+        assert_eq!(f.window(Some("2023-03-01")), Window::In);
+        assert_eq!(f.window(Some("2023-03-31T23:00:00+08:00")), Window::In);
+        assert_eq!(f.window(Some("2023-04-01")), Window::Newer);
+        // 无法识别的日期由详情页再次检查
+        assert_eq!(f.window(None), Window::In);
+        assert_eq!(f.window(Some("March 1, 2023")), Window::In);
+        // 只限制一端
+        assert_eq!(window(Some("2023-03-01"), None).window(Some("2099-01-01")), Window::In);
+        assert_eq!(window(None, Some("2023-03-31")).window(Some("2000-01-01")), Window::In);
+        assert_eq!(Filter::default().window(Some("2023-03-01")), Window::In);
+    }
+
+    #[test]
+    fn check_date_reasons() {
+        let f = window(Some("2023-03-01"), Some("2023-03-31"));
+        let skip = f.check_date(Some("2023-02-01")).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("since", "dated 2023-02-01 before 2023-03-01"));
+        let skip = f.check_date(Some("2023-04-01")).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("until", "dated 2023-04-01 after 2023-03-31"));
+        assert!(f.check_date(Some("2023-03-15")).is_none());
+    }
+
+    #[test]
+    fn check_title_reasons() {
+        let f = Filter {
+            include: vec![Regex::new("(?i)cos").unwrap(), Regex::new("原神").unwrap()],
+            exclude: vec![Regex::new("合集").unwrap()],
+            ..Default::default()
+        };
+        assert!(f.check_title("COS 原神 甘雨").is_none());
+        let skip = f.check_title("写真").unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("include", r#"title matches none of ["(?i)cos", "原神"]"#));
+        let skip = f.check_title("cos 合集").unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("exclude", "title matches 合集"));
+        assert!(Filter::default().check_title("任意标题").is_none());
+    }
+
+    #[test]
+    fn check_media_reasons() {
+        let f = Filter { min_images: Some(5), max_images: Some(50), ..Default::default() };
+        assert!(f.check_media(5, 0).is_none());
+        assert!(f.check_media(50, 1).is_none());
+        let skip = f.check_media(4, 2).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("min-images", "4 images, fewer than 5"));
+        let skip = f.check_media(51, 0).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("max-images", "51 images, more than 50"));
+
+        let videos = Filter { media: MediaKind::Videos, ..Default::default() };
+        assert!(videos.check_media(0, 1).is_none());
+        let skip = videos.check_media(10, 0).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("media", "10 images and 0 videos, none of Videos"));
+        let images = Filter { media: MediaKind::Images, ..Default::default() };
+        assert!(images.check_media(1, 0).is_none());
+        let skip = images.check_media(0, 3).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("media", "0 images and 3 videos, none of Images"));
+        let skip = Filter::default().check_media(0, 0).unwrap();
+        assert_eq!((skip.rule, skip.detail.as_str()), ("media", "0 images and 0 videos, none of Both"));
+    }
+
+    #[test]
+    fn parse_date_checks_days_in_month() {
+        assert_eq!(parse_date("2023-01-05T12:00:00+08:00").as_deref(), Some("2023-01-05"));
+        assert_eq!(parse_date("2023-02-30"), None);
+        assert_eq!(parse_date("2023-04-31"), None);
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-02-29").as_deref(), Some("2024-02-29"));
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert_eq!(parse_date("2000-02-29").as_deref(), Some("2000-02-29"));
+        assert_eq!(parse_date("2023-13-01"), None);
+        assert_eq!(parse_date("2023/01/01"), None);
+    }
+}
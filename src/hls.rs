@@ -0,0 +1,219 @@
+use crate::event::{Events, Meter};
+use crate::media;
+use crate::session::Session;
+use log::{info, warn};
+use reqwest::Url;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+// 媒体播放列表: fMP4 初始化分段和媒体分段
+struct Playlist
+{
+    init: Option<Url>,
+    segments: Vec<Url>,
+}
+
+// 下载 HLS 播放列表的所有分段并合并为单个文件
+// ts 分段有 ffmpeg 时转封装为 mp4, 否则保留 ts
+pub async fn download(session: &Session, events: &Events, url: &str, folder: &Path, index: usize, width: usize) -> anyhow::Result<(String, u64)> {
+    let (base, text) = fetch(session, url).await?;
+    let (base, text) = match best_variant(&base, &text) {
+        Some(variant) => {
+            info!("use hls variant {} of {}", variant, url);
+            fetch(session, variant.as_str()).await?
+        }
+        None => (base, text),
+    };
+    let list = parse(&base, &text)?;
+
+    let part = folder.join(format!(".{:0width$}.part", index, width = width));
+    let result = async {
+        let mut file = tokio::fs::File::create(&part).await?;
+        let mut size = 0u64;
+        let mut meter = Meter::new(events, url, folder, None);
+        for seg in list.init.iter().chain(list.segments.iter()) {
+            let mut res = session.http_get(seg.as_str()).await?.error_for_status()?;
+            while let Some(chunk) = session.chunk(&mut res).await? {
+                file.write_all(&chunk).await?;
+                size += chunk.len() as u64;
+                meter.add(chunk.len() as u64);
+            }
+        }
+        file.flush().await?;
+        let ext = if list.init.is_some() { "mp4" } else { "ts" };
+        let name = media::file_name(index, width, url, ext);
+        tokio::fs::rename(&part, folder.join(&name)).await?;
+        if ext == "ts" {
+            if let Some(remuxed) = remux(folder, &name).await {
+                return Ok(remuxed);
+            }
+        }
+        Ok((name, size))
+    }.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
+async fn fetch(session: &Session, url: &str) -> anyhow::Result<(Url, String)> {
+    let res = session.http_get(url).await?.error_for_status()?;
+    let base = res.url().clone();
+    let text = session.text(res).await?;
+    if !text.trim_start().starts_with("#EXTM3U") {
+        anyhow::bail!("{} is not a m3u8 playlist", url);
+    }
+    Ok((base, text))
+}
+
+// 主播放列表中码率最高的子列表, 媒体播放列表返回 None
+fn best_variant(base: &Url, text: &str) -> Option<Url> {
+    let mut best: Option<(u64, Url)> = None;
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let bandwidth = attr(attrs, "BANDWIDTH").and_then(|b| b.parse().ok()).unwrap_or(0);
+            let uri = lines.by_ref().find(|l| !l.is_empty() && !l.starts_with('#'));
+            if let Some(u) = uri.and_then(|u| base.join(u).ok()) {
+                if best.as_ref().is_none_or(|(b, _)| bandwidth > *b) {
+                    best = Some((bandwidth, u));
+                }
+            }
+        }
+    }
+    best.map(|(_, u)| u)
+}
+
+fn parse(base: &Url, text: &str) -> anyhow::Result<Playlist> {
+    let mut list = Playlist { init: None, segments: Vec::new() };
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+            let method = attr(attrs, "METHOD").unwrap_or_default();
+            if method != "NONE" {
+                anyhow::bail!("encrypted hls stream ({}) is not supported", method);
+            }
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            if list.init.is_some() {
+                warn!("multiple EXT-X-MAP in {}, only the first is used", base);
+                continue;
+            }
+            let uri = attr(attrs, "URI").ok_or_else(|| anyhow::anyhow!("EXT-X-MAP without URI in {}", base))?;
+            list.init = Some(base.join(&uri)?);
+        } else if !line.starts_with('#') {
+            list.segments.push(base.join(line)?);
+        }
+    }
+    if list.segments.is_empty() {
+        anyhow::bail!("no segment in playlist {}", base);
+    }
+    Ok(list)
+}
+
+// 属性列表中的值, 如 BANDWIDTH=1280000,URI="a.mp4"
+fn attr(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (v, n) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (v, n.trim_start_matches(','))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        if key.trim() == name {
+            return Some(value.to_string());
+        }
+        rest = next;
+    }
+    None
+}
+
+// 使用 ffmpeg 将 ts 转封装为 mp4, 失败时保留原文件
+async fn remux(folder: &Path, name: &str) -> Option<(String, u64)> {
+    let src = folder.join(name);
+    let mp4 = format!("{}.mp4", name.trim_end_matches(".ts"));
+    let dst = folder.join(&mp4);
+    let status = tokio::process::Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-y", "-i"])
+        .arg(&src)
+        .args(["-c", "copy", "-bsf:a", "aac_adtstoasc"])
+        .arg(&dst)
+        .stdin(std::process::Stdio::null())
+        .status().await;
+    match status {
+        Ok(s) if s.success() => {
+            let size = tokio::fs::metadata(&dst).await.ok()?.len();
+            let _ = tokio::fs::remove_file(&src).await;
+            Some((mp4, size))
+        }
+        Ok(s) => {
+            warn!("remux {} to mp4 error: {}, keep ts", src.display(), s);
+            let _ = tokio::fs::remove_file(&dst).await;
+            None
+        }
+        Err(e) => {
+            info!("ffmpeg not available ({}), keep {}", e, src.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/v/1/index.m3u8").unwrap()
+    }
+
+    #[test]
+    fn attr_handles_quoted_commas() {
+        let attrs = r#"BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2",RESOLUTION=1280x720"#;
+        assert_eq!(attr(attrs, "CODECS").as_deref(), Some("avc1.4d401f,mp4a.40.2"));
+        assert_eq!(attr(attrs, "RESOLUTION").as_deref(), Some("1280x720"));
+        assert_eq!(attr(attrs, "BANDWIDTH").as_deref(), Some("1280000"));
+        assert_eq!(attr(attrs, "URI"), None);
+    }
+
+    #[test]
+    fn best_variant_picks_highest_bandwidth() {
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1,mp4a\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1920x1080\n\
+            /hd/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1200000\n\
+            mid/index.m3u8\n";
+        assert_eq!(best_variant(&base(), text).unwrap().as_str(), "https://cdn.example.com/hd/index.m3u8");
+    }
+
+    #[test]
+    fn best_variant_without_bandwidth() {
+        let text = "#EXTM3U\n#EXT-X-STREAM-INF:RESOLUTION=640x360\na.m3u8\n#EXT-X-STREAM-INF:RESOLUTION=1280x720\nb.m3u8\n";
+        assert_eq!(best_variant(&base(), text).unwrap().as_str(), "https://cdn.example.com/v/1/a.m3u8");
+        assert!(best_variant(&base(), "#EXTM3U\n#EXTINF:4.0,\nseg0.ts\n").is_none());
+    }
+
+    #[test]
+    fn parse_media_playlist() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-KEY:METHOD=NONE\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4.0,\nseg0.m4s\n#EXTINF:4.0,\n../2/seg1.m4s\n\
+            #EXTINF:2.0,\nhttps://other.example.com/seg2.m4s\n#EXT-X-ENDLIST\n";
+        let list = parse(&base(), text).unwrap();
+        assert_eq!(list.init.unwrap().as_str(), "https://cdn.example.com/v/1/init.mp4");
+        let segs: Vec<_> = list.segments.iter().map(Url::as_str).collect();
+        assert_eq!(segs, [
+            "https://cdn.example.com/v/1/seg0.m4s",
+            "https://cdn.example.com/v/2/seg1.m4s",
+            "https://other.example.com/seg2.m4s",
+        ]);
+    }
+
+    #[test]
+    fn parse_rejects_encrypted_and_empty() {
+        let text = "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n#EXTINF:4.0,\nseg0.ts\n";
+        assert!(parse(&base(), text).is_err());
+        assert!(parse(&base(), "#EXTM3U\n#EXT-X-ENDLIST\n").is_err());
+    }
+}
@@ -0,0 +1,27 @@
+// cosjun.cn 爬取与打包上传, cospull 和 cosdup 两个命令行工具都基于此库
+//
+// 爬取: Cos::builder(output).build()? 得到客户端, posts() 返回列表中帖子的异步流,
+// fetch_post() 解析详情页, download_post() 下载帖子中的文件, 进度通过 Events 回调或通道报告.
+// 打包: Dup::builder(src, out).build()? 下载 info.txt 中的文件后分块压缩, 校验并上传.
+
+pub mod api;
+pub mod check;
+pub mod disk;
+pub mod dup;
+pub mod event;
+pub mod filter;
+pub mod hls;
+pub mod media;
+pub mod naming;
+pub mod plan;
+pub mod post;
+pub mod report;
+pub mod session;
+pub mod similar;
+pub mod store;
+
+pub use api::{Cos, CosBuilder, CosItem, Listed, Listing, TagSummary};
+pub use dup::{Dup, DupBuilder};
+pub use event::{Event, Events};
+pub use filter::Filter;
+pub use post::PostDetail;
@@ -0,0 +1,308 @@
+use crate::naming;
+use crate::session::Session;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use visdom::Vis;
+
+// 已下载的媒体文件, 记录在 meta.json 中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaFile
+{
+    // 在图集中的序号, 从 1 开始
+    pub index: usize,
+    pub url: String,
+    // 相对帖子目录的路径, 如 imgs/001_foo.jpg
+    pub file: String,
+    pub size: u64,
+}
+
+// 识别的媒体扩展名
+const IMAGE_EXTS: [&str; 8] = ["jpg", "jpeg", "png", "gif", "webp", "bmp", "avif", "heic"];
+const VIDEO_EXTS: [&str; 7] = ["mp4", "webm", "mov", "m4v", "mkv", "ts", "flv"];
+
+// 识别文件类型读取的文件头长度, 下载时和检查时相同
+pub const SNIFF_LEN: usize = 512;
+
+// 帖子目录中的元数据文件, 检查和去重时跳过
+pub const META_FILES: [&str; 2] = ["meta.json", "info.txt"];
+
+// 文件名中基础名称的最大字节数
+const BASE_MAX_BYTES: usize = 120;
+
+pub fn is_media_ext(ext: &str) -> bool {
+    IMAGE_EXTS.contains(&ext) || VIDEO_EXTS.contains(&ext)
+}
+
+pub fn is_image_ext(ext: &str) -> bool {
+    IMAGE_EXTS.contains(&ext)
+}
+
+// URL 路径最后一段, 去掉查询参数
+fn last_segment(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url).trim_end_matches('/');
+    let path = path.split_once("://").map(|(_, p)| p).unwrap_or(path);
+    match path.split_once('/') {
+        Some((_, p)) => p.rsplit('/').next().unwrap_or(""),
+        None => "",
+    }
+}
+
+// URL 中可识别的扩展名
+pub fn ext_from_url(url: &str) -> Option<String> {
+    let seg = last_segment(url);
+    let (_, ext) = seg.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    if is_media_ext(&ext) {
+        Some(if ext == "jpeg" { "jpg".to_string() } else { ext })
+    } else {
+        None
+    }
+}
+
+// HLS 播放列表
+pub fn is_hls(url: &str) -> bool {
+    last_segment(url).to_lowercase().ends_with(".m3u8")
+}
+
+pub fn ext_from_content_type(content_type: &str) -> Option<String> {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    let ext = match mime.as_str() {
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/avif" => "avif",
+        "image/heic" => "heic",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "video/x-m4v" => "m4v",
+        "video/x-matroska" => "mkv",
+        "video/mp2t" => "ts",
+        "video/x-flv" => "flv",
+        _ => return None,
+    };
+    Some(ext.to_string())
+}
+
+// 根据文件头识别扩展名
+pub fn sniff_ext(head: &[u8]) -> Option<String> {
+    let ext = if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "jpg"
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "png"
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        "gif"
+    } else if head.len() >= 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        "webp"
+    } else if head.starts_with(b"BM") {
+        "bmp"
+    } else if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        match &head[8..12] {
+            b"avif" | b"avis" => "avif",
+            b"heic" | b"heix" | b"mif1" => "heic",
+            b"qt  " => "mov",
+            b"M4V " => "m4v",
+            _ => "mp4",
+        }
+    } else if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        // EBML, webm 与 mkv 头相同
+        "webm"
+    } else if head.starts_with(b"FLV") {
+        "flv"
+    } else if head.first() == Some(&0x47) && head.get(188) == Some(&0x47) {
+        "ts"
+    } else {
+        return None;
+    };
+    Some(ext.to_string())
+}
+
+// 原始文件基础名称, 去掉扩展名和 CDN 缩放后缀 -1024x683
+pub fn base_name(url: &str) -> String {
+    let seg = last_segment(url);
+    let seg = url_escape::decode(seg).to_string();
+    let stem = match seg.rsplit_once('.') {
+        Some((stem, ext)) if is_media_ext(&ext.to_lowercase()) || ext.eq_ignore_ascii_case("m3u8") => stem.to_string(),
+        _ => seg.clone(),
+    };
+    let stem = strip_size_suffix(&stem);
+    let name = naming::sanitize(stem, BASE_MAX_BYTES);
+    if name == "_" { "media".to_string() } else { name }
+}
+
+// 去掉 WordPress 缩略图后缀 -WxH
+pub fn strip_size_suffix(stem: &str) -> &str {
+    if let Some((base, size)) = stem.rsplit_once('-') {
+        if let Some((w, h)) = size.split_once('x') {
+            let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+            if !base.is_empty() && digits(w) && digits(h) {
+                return base;
+            }
+        }
+    }
+    stem
+}
+
+// 序号补零位数, 至少 3 位
+pub fn index_width(total: usize) -> usize {
+    total.to_string().len().max(3)
+}
+
+// 生成文件名: 补零序号_基础名称.扩展名
+pub fn file_name(index: usize, width: usize, url: &str, ext: &str) -> String {
+    format!("{:0width$}_{}.{}", index, base_name(url), ext, width = width)
+}
+
+// 图集中一项的候选地址, 按优先级排列
+#[derive(Debug, Clone, Default)]
+pub struct Candidates
+{
+    pub urls: Vec<String>,
+    // 链接指向的附件页
+    pub page: Option<String>,
+}
+
+impl Candidates {
+    pub fn push(&mut self, url: String) {
+        if !self.urls.contains(&url) {
+            self.urls.push(url);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty() && self.page.is_none()
+    }
+}
+
+// srcset 中宽度或倍率最大的地址
+pub fn largest_srcset(srcset: &str) -> Option<String> {
+    srcset.split(',')
+        .filter_map(|item| {
+            let mut parts = item.split_whitespace();
+            let url = parts.next()?;
+            let size = parts.next()
+                .and_then(|d| d.trim_end_matches(['w', 'x']).parse::<f64>().ok())
+                .unwrap_or(1.0);
+            Some((url.to_string(), size))
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(url, _)| url)
+}
+
+// 去掉 -WxH 缩放后缀后的原图地址, 没有后缀时返回 None
+pub fn original_url(url: &str) -> Option<String> {
+    let (path, rest) = match url.find(['?', '#']) {
+        Some(i) => url.split_at(i),
+        None => (url, ""),
+    };
+    let (dir, seg) = path.rsplit_once('/')?;
+    let (stem, ext) = seg.rsplit_once('.')?;
+    let base = strip_size_suffix(stem);
+    if base.len() == stem.len() {
+        return None;
+    }
+    Some(format!("{}/{}.{}{}", dir, base, ext, rest))
+}
+
+// 确定图集中一项的原图地址
+// 优先使用链接和 srcset 中的直接地址, 缩略图存在原图时使用原图, 只有附件页时跟随附件页
+pub async fn resolve(session: &Session, c: &Candidates) -> Option<String> {
+    let mut best = c.urls.iter().find(|u| ext_from_url(u).is_some()).cloned();
+    if best.is_none() {
+        if let Some(page) = &c.page {
+            best = resolve_page(session, page).await;
+        }
+    }
+    let best = best.or_else(|| c.urls.first().cloned())?;
+    if let Some(orig) = original_url(&best) {
+        if c.urls.contains(&orig) || exists(session, &orig).await {
+            info!("use original {} instead of {}", &orig, &best);
+            return Some(orig);
+        }
+    }
+    Some(best)
+}
+
+// 从附件页中找到实际文件
+async fn resolve_page(session: &Session, page: &str) -> Option<String> {
+    let html = match session.http_get(page).await {
+        Ok(res) => session.text(res).await.ok()?,
+        Err(e) => {
+            warn!("get attachment page {} error: {}", page, e);
+            return None;
+        }
+    };
+    let html = Vis::load(html).ok()?;
+    let area = html.find(".entry-attachment, .attachment, .wp-block-image, .entry-content");
+    let c = crate::post::candidates(&area.find("a").first(), &area.find("img").first());
+    c.urls.into_iter()
+        .chain(html.find("meta[property='og:image']").attr("content").map(|v| v.to_string()))
+        .find(|u| ext_from_url(u).is_some())
+}
+
+async fn exists(session: &Session, url: &str) -> bool {
+    match session.http_head(url).await {
+        Ok(res) => res.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ext_from_url_ignores_query_and_unknown_exts() {
+        assert_eq!(ext_from_url("https://a.com/x/p.JPEG?w=100#top").as_deref(), Some("jpg"));
+        assert_eq!(ext_from_url("https://a.com/v/clip.mp4/").as_deref(), Some("mp4"));
+        assert_eq!(ext_from_url("https://a.com/image?id=1.jpg"), None);
+        assert_eq!(ext_from_url("https://a.com/page.html"), None);
+        assert_eq!(ext_from_url("https://a.jpg"), None);
+    }
+
+    #[test]
+    fn file_name_strips_size_suffix_and_query() {
+        let url = "https://a.com/wp-content/uploads/2023/05/photo-1024x683.jpg?ver=2";
+        assert_eq!(file_name(7, 3, url, "jpg"), "007_photo.jpg");
+        assert_eq!(file_name(12, index_width(1200), "https://a.com/%E5%9B%BE.png", "png"), "0012_图.png");
+        assert_eq!(file_name(1, 3, "https://a.com/download", "mp4"), "001_download.mp4");
+        assert_eq!(file_name(1, 3, "https://a.com/", "jpg"), "001_media.jpg");
+        assert_eq!(file_name(2, 3, "https://a.com/live.m3u8", "mp4"), "002_live.mp4");
+    }
+
+    #[test]
+    fn strip_size_suffix_only_strips_dimensions() {
+        assert_eq!(strip_size_suffix("photo-1024x683"), "photo");
+        assert_eq!(strip_size_suffix("my-photo-300x200"), "my-photo");
+        assert_eq!(strip_size_suffix("photo-1024"), "photo-1024");
+        assert_eq!(strip_size_suffix("photo-axb"), "photo-axb");
+        assert_eq!(strip_size_suffix("-1024x683"), "-1024x683");
+    }
+
+    #[test]
+    fn sniff_and_content_type() {
+        assert_eq!(sniff_ext(&[0xFF, 0xD8, 0xFF, 0xE0]).as_deref(), Some("jpg"));
+        assert_eq!(sniff_ext(b"\0\0\0\x18ftypavif").as_deref(), Some("avif"));
+        assert_eq!(sniff_ext(b"<html>"), None);
+        assert_eq!(ext_from_content_type("image/jpeg; charset=binary").as_deref(), Some("jpg"));
+        assert_eq!(ext_from_content_type("text/html"), None);
+    }
+
+    #[test]
+    fn largest_srcset_picks_widest() {
+        let srcset = "https://a.com/p-300x200.jpg 300w, https://a.com/p-1024x683.jpg 1024w, https://a.com/p-768x512.jpg 768w";
+        assert_eq!(largest_srcset(srcset).as_deref(), Some("https://a.com/p-1024x683.jpg"));
+        assert_eq!(largest_srcset("https://a.com/2x.jpg 2x, https://a.com/1x.jpg").as_deref(), Some("https://a.com/2x.jpg"));
+        assert_eq!(largest_srcset(""), None);
+    }
+
+    #[test]
+    fn original_url_keeps_query() {
+        assert_eq!(original_url("https://a.com/up/p-1024x683.jpg?ver=2").as_deref(), Some("https://a.com/up/p.jpg?ver=2"));
+        assert_eq!(original_url("https://a.com/up/p-1024x683.webp#x").as_deref(), Some("https://a.com/up/p.webp#x"));
+        assert_eq!(original_url("https://a.com/up/p.jpg"), None);
+        assert_eq!(original_url("https://a.com/up/download"), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::path::PathBuf;
+
+// 默认目录模板, 与之前 <tag>/<title> 的目录结构一致
+pub const DEFAULT_TEMPLATE: &str = "{tag}/{title}";
+// 单级目录名最大字节数, 多数文件系统上限为 255
+pub const DEFAULT_MAX_BYTES: usize = 200;
+
+const PLACEHOLDERS: [&str; 4] = ["tag", "date", "id", "title"];
+
+// Windows / Samba 保留名称
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// 模板中可用的字段
+pub struct NameFields<'a>
+{
+    pub tag: &'a str,
+    pub date: Option<&'a str>,
+    pub id: &'a str,
+    pub title: &'a str,
+    // 追加在截断后的标题末尾, 用于区分同名帖子
+    pub suffix: Option<&'a str>,
+}
+
+// 帖子目录命名策略
+#[derive(Debug, Clone)]
+pub struct Naming
+{
+    template: String,
+    max_bytes: usize,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self { template: DEFAULT_TEMPLATE.to_string(), max_bytes: DEFAULT_MAX_BYTES }
+    }
+}
+
+impl Naming {
+    pub fn new(template: &str, max_bytes: usize) -> anyhow::Result<Self> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}')
+                .ok_or_else(|| anyhow::anyhow!("unclosed placeholder in template {}", template))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                anyhow::bail!("unknown placeholder {{{}}} in template {}, use one of {:?}", name, template, PLACEHOLDERS);
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if !template.contains("{title}") && !template.contains("{id}") {
+            anyhow::bail!("template {} needs {{title}} or {{id}}", template);
+        }
+        if max_bytes < 16 {
+            anyhow::bail!("max bytes {} is too small", max_bytes);
+        }
+        Ok(Self { template: template.to_string(), max_bytes })
+    }
+
+    // 模板需要帖子详情页中的发布日期
+    pub fn needs_date(&self) -> bool {
+        self.template.contains("{date}")
+    }
+
+    // 模板已包含帖子 ID, 同名目录一定是同一个帖子
+    pub fn has_id(&self) -> bool {
+        self.template.contains("{id}")
+    }
+
+    // 按模板生成相对目录, 每级目录单独清理和截断
+    pub fn render(&self, fields: &NameFields) -> PathBuf {
+        let date = fields.date.map(short_date).unwrap_or_else(|| "nodate".to_string());
+        let mut path = PathBuf::new();
+        let suffix = fields.suffix.map(|s| format!("-{}", s)).unwrap_or_default();
+        for part in self.template.split('/').filter(|p| !p.is_empty()) {
+            let part = part
+                .replace("{tag}", &fields.tag.replace('/', "_"))
+                .replace("{date}", &date)
+                .replace("{id}", fields.id);
+            // 优先截断标题, 保留其他字段和后缀
+            let room = self.max_bytes.saturating_sub(part.replace("{title}", "").len() + suffix.len());
+            let title = sanitize(&fields.title.replace('/', "_"), room.max(1));
+            let part = part.replace("{title}", &format!("{}{}", title, suffix));
+            path.push(sanitize(&part, self.max_bytes));
+        }
+        path
+    }
+}
+
+// 从帖子 URL 中取 ID, 如 https://www.cosjun.cn/12345.html 或 ?p=12345
+pub fn post_id(url: &str) -> String {
+    if let Some((_, query)) = url.split_once("?p=") {
+        let id: String = query.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if !id.is_empty() {
+            return id;
+        }
+    }
+    let path = url.split(['?', '#']).next().unwrap_or(url).trim_end_matches('/');
+    let last = path.rsplit('/').next().unwrap_or(path);
+    let stem = last.split('.').next().unwrap_or(last);
+    if !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return stem.to_string();
+    }
+    // 无法识别时使用 URL 的 FNV 哈希
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in url.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", h)
+}
+
+// 发布日期只保留 YYYY-MM-DD
+fn short_date(date: &str) -> String {
+    let d: String = date.trim().chars().take(10).collect();
+    if d.len() == 10 && d.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() }) {
+        d
+    } else {
+        sanitize(date, 32)
+    }
+}
+
+// 清理不能用于文件名的字符, 按字节截断
+pub fn sanitize(name: &str, max_bytes: usize) -> String {
+    let mut s = String::with_capacity(name.len());
+    let mut last_space = false;
+    for c in name.chars() {
+        let c = match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        };
+        // 合并连续空白
+        if c.is_whitespace() {
+            if !last_space {
+                s.push(' ');
+            }
+            last_space = true;
+        } else {
+            s.push(c);
+            last_space = false;
+        }
+    }
+    let mut s = truncate_bytes(s.trim(), max_bytes)
+        .trim_end_matches(['.', ' '])
+        .trim_start_matches(['.', ' '])
+        .to_string();
+    if s.is_empty() {
+        s = "_".to_string();
+    }
+    let stem = s.split('.').next().unwrap_or("").to_uppercase();
+    if RESERVED.contains(&stem.as_str()) {
+        // 加前缀后仍不超过字节上限
+        s = format!("_{}", truncate_bytes(&s, max_bytes.saturating_sub(1)));
+    }
+    s
+}
+
+// 在字符边界按字节截断
+pub fn truncate_bytes(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields<'a>(title: &'a str) -> NameFields<'a> {
+        NameFields { tag: "cos/er", date: Some("2023-05-06T10:00:00"), id: "123", title, suffix: None }
+    }
+
+    #[test]
+    fn sanitize_reserved_and_dots() {
+        assert_eq!(sanitize("CON", 200), "_CON");
+        assert_eq!(sanitize("con.txt", 200), "_con.txt");
+        assert_eq!(sanitize("CONSOLE", 200), "CONSOLE");
+        assert_eq!(sanitize("..", 200), "_");
+        assert_eq!(sanitize(" name. . ", 200), "name");
+        assert_eq!(sanitize("a/b:c*d", 200), "a_b_c_d");
+        assert_eq!(sanitize("a\t\n  b", 200), "a b");
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        let title = "汉".repeat(100);
+        let s = sanitize(&title, 200);
+        assert_eq!(s.len(), 198);
+        assert!(s.chars().all(|c| c == '汉'));
+        let s = sanitize(&format!("CON.{}", "a".repeat(300)), 200);
+        assert_eq!(s.len(), 200);
+        assert!(s.starts_with("_CON."));
+    }
+
+    #[test]
+    fn render_keeps_components_within_max_bytes() {
+        let naming = Naming::new("{tag}/{date}-{id}-{title}", 64).unwrap();
+        let title = "很长的标题".repeat(20);
+        let f = NameFields { suffix: Some("2"), ..fields(&title) };
+        let path = naming.render(&f);
+        let parts: Vec<_> = path.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(parts[0], "cos_er");
+        assert!(parts[1].starts_with("2023-05-06-123-很长的标题"));
+        assert!(parts[1].ends_with("-2"));
+        assert!(parts.iter().all(|p| p.len() <= 64));
+        assert_eq!(Naming::default().render(&fields("..")), PathBuf::from("cos_er/_"));
+        assert_eq!(naming.render(&NameFields { date: None, ..fields("t") }), PathBuf::from("cos_er/nodate-123-t"));
+    }
+
+    #[test]
+    fn new_rejects_bad_templates() {
+        assert!(Naming::new("{tag}/{name}", 200).is_err());
+        assert!(Naming::new("{tag}/{title", 200).is_err());
+        assert!(Naming::new("{tag}/{date}", 200).is_err());
+        assert!(Naming::new("{title}", 8).is_err());
+        assert!(Naming::new("{date}/{id}", 200).unwrap().needs_date());
+    }
+
+    #[test]
+    fn post_id_from_url() {
+        assert_eq!(post_id("https://www.cosjun.cn/12345.html"), "12345");
+        assert_eq!(post_id("https://www.cosjun.cn/?p=678&foo=1"), "678");
+        assert_eq!(post_id("https://www.cosjun.cn/archives/some-post/"), "some-post");
+        let hashed = post_id("https://www.cosjun.cn/标题.html");
+        assert_eq!(hashed.len(), 16);
+        assert_eq!(hashed, post_id("https://www.cosjun.cn/标题.html"));
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::media;
+use crate::session;
+
+// --dry-run 的下载计划: 只解析列表页和详情页, 不创建目录也不下载文件
+#[derive(Debug, Default, Serialize)]
+pub struct Plan
+{
+    pub listings: Vec<ListingPlan>,
+    pub posts: usize,
+    pub files: usize,
+    // 已知大小的文件总字节数
+    pub bytes: u64,
+    // HEAD 请求没有返回大小的文件数
+    pub unknown: usize,
+}
+
+// 一个标签, 搜索或 get 的计划
+#[derive(Debug, Default, Serialize)]
+pub struct ListingPlan
+{
+    pub name: String,
+    pub pages: Vec<PagePlan>,
+    pub posts: Vec<PostPlan>,
+    // 被筛选规则跳过的帖子数
+    pub filtered: usize,
+    // 已下载过的帖子数
+    pub skipped: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PagePlan
+{
+    pub page: i32,
+    pub url: String,
+    // 列表页中的帖子数
+    pub found: usize,
+    // 通过标题和日期筛选的帖子数
+    pub queued: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostPlan
+{
+    pub url: String,
+    pub title: String,
+    pub date: Option<String>,
+    // 将要创建的帖子目录
+    pub dir: String,
+    pub images: Vec<MediaPlan>,
+    pub videos: Vec<MediaPlan>,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaPlan
+{
+    pub url: String,
+    pub size: Option<u64>,
+}
+
+impl Plan {
+    // 新的列表计划, 之后的帖子记录在最后一个列表中
+    pub fn begin(&mut self, name: &str) {
+        self.listings.push(ListingPlan { name: name.to_string(), ..Default::default() });
+    }
+
+    pub fn current(&mut self) -> &mut ListingPlan {
+        if self.listings.is_empty() {
+            self.begin("");
+        }
+        self.listings.last_mut().unwrap()
+    }
+
+    pub fn add_post(&mut self, post: PostPlan) {
+        self.posts += 1;
+        for m in post.images.iter().chain(post.videos.iter()) {
+            self.files += 1;
+            match m.size {
+                Some(size) => self.bytes += size,
+                None => self.unknown += 1,
+            }
+        }
+        self.current().posts.push(post);
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        for l in &self.listings {
+            println!("<{}>", l.name);
+            for p in &l.pages {
+                println!("  page {:>4}: {:>3} posts, {:>3} queued  {}", p.page, p.found, p.queued, p.url);
+            }
+            for p in &l.posts {
+                println!("  {} ({} images, {} videos, {} bytes)", p.dir, p.images.len(), p.videos.len(), p.bytes);
+                for m in p.images.iter().chain(p.videos.iter()) {
+                    match m.size {
+                        Some(size) => println!("    {:>12} {}", size, m.url),
+                        None => println!("    {:>12} {}", "?", m.url),
+                    }
+                }
+            }
+            println!("  {} posts to download, {} filtered, {} already downloaded", l.posts.len(), l.filtered, l.skipped);
+        }
+        println!("total {} posts, {} files, {} bytes, {} files of unknown size", self.posts, self.files, self.bytes, self.unknown);
+    }
+}
+
+// HEAD 请求获取文件大小, m3u8 播放列表的大小不代表视频大小
+pub async fn media_plan(session: &session::Session, url: String) -> MediaPlan {
+    if media::is_hls(&url) {
+        return MediaPlan { url, size: None };
+    }
+    let size = match session.http_head(&url).await {
+        // HEAD 响应没有响应体, 直接读取 Content-Length 头
+        Ok(res) if res.status().is_success() => res.headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()),
+        _ => None,
+    };
+    MediaPlan { url, size }
+}
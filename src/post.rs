@@ -0,0 +1,376 @@
+use crate::media::{self, Candidates, MediaFile};
+use crate::naming;
+use regex::Regex;
+use reqwest::Url;
+use serde::Serialize;
+use visdom::types::Elements;
+use visdom::Vis;
+
+// 帖子信息, 写入帖子目录 meta.json
+#[derive(Debug, Default, Serialize)]
+pub struct PostMeta
+{
+    pub title: String,
+    pub url: String,
+    pub tag: String,
+    // 发布时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coser: Option<String>,
+    // 角色
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub character: Option<String>,
+    // 作品
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // 封面图片
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<String>,
+    pub image_count: usize,
+    pub video_count: usize,
+    // 已下载文件, 按图集顺序
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<MediaFile>,
+    // 所有文件下载成功后为 true, 为 false 时下次运行继续下载缺失的文件
+    pub complete: bool,
+    // 无法下载的媒体, 如第三方播放器
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported: Vec<Unsupported>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Unsupported
+{
+    pub url: String,
+    pub reason: String,
+}
+
+// 帖子详情页解析结果
+pub struct PostDetail
+{
+    // 图集中每张图片的候选地址
+    pub imgs: Vec<Candidates>,
+    pub videos: Vec<String>,
+    pub meta: PostMeta,
+}
+
+// 描述最多保留的字符数
+const DESCRIPTION_LIMIT: usize = 500;
+
+// 解析帖子详情页
+pub fn parse(html: &Elements, title: &str, url: &str, tag: &str) -> PostDetail {
+    let imgs = gallery(html);
+    let (videos, unsupported) = videos(html, url);
+
+    let content = content_lines(html);
+    // 直接下载帖子时没有列表页中的标题
+    let title = if title.is_empty() { page_title(html).unwrap_or_else(|| naming::post_id(url)) } else { title.to_string() };
+    let mut meta = PostMeta {
+        title,
+        url: url.to_string(),
+        tag: tag.to_string(),
+        date: meta_content(html, "meta[property='article:published_time']")
+            .or_else(|| first_attr(html, "time[datetime]", "datetime"))
+            .or_else(|| first_text(html, ".entry-meta .meta-date")),
+        author: meta_content(html, "meta[name='author']")
+            .or_else(|| first_text(html, "a[rel~='author']"))
+            .or_else(|| first_text(html, ".entry-meta .meta-author a")),
+        categories: texts(html, "a[rel~='category'], .entry-meta .meta-category a"),
+        tags: texts(html, "a[rel='tag'], .entry-tags a, .post-tags a"),
+        description: meta_content(html, "meta[name='description']")
+            .or_else(|| meta_content(html, "meta[property='og:description']"))
+            .or_else(|| first_text(html, ".entry-content p"))
+            .map(|s| s.chars().take(DESCRIPTION_LIMIT).collect()),
+        cover: meta_content(html, "meta[property='og:image']")
+            .or_else(|| imgs.first().and_then(|c| c.urls.first().cloned())),
+        image_count: imgs.len(),
+        video_count: videos.len(),
+        unsupported,
+        ..Default::default()
+    };
+    // 正文中常见 "COSER：xxx" "角色：xxx" "作品：xxx" 格式
+    meta.coser = labeled(&content, &["coser", "cn", "出镜"]);
+    meta.character = labeled(&content, &["角色", "character"]);
+    meta.series = labeled(&content, &["作品", "出处", "系列", "series"]);
+
+    PostDetail { imgs, videos, meta }
+}
+
+// 详情页标题
+fn page_title(html: &Elements) -> Option<String> {
+    first_text(html, "h1.entry-title")
+        .or_else(|| meta_content(html, "meta[property='og:title']"))
+        .or_else(|| first_text(html, "title"))
+}
+
+// 图集中每一项的候选地址: 链接, 懒加载属性, srcset 最大项, src
+fn gallery(html: &Elements) -> Vec<Candidates> {
+    let mut v = Vec::new();
+    html.find(".gallery-icon").into_iter().for_each(|icon| {
+        let icon = Vis::dom(&icon);
+        let c = candidates(&icon.find("a").first(), &icon.find("img").first());
+        if !c.is_empty() {
+            v.push(c);
+        }
+    });
+    v
+}
+
+// 按优先级收集候选地址, 链接不是媒体文件时视为附件页
+pub fn candidates(link: &Elements, img: &Elements) -> Candidates {
+    let mut c = Candidates::default();
+    if let Some(href) = link.attr("href").map(|v| v.to_string().trim().to_string()).filter(|s| !s.is_empty()) {
+        if media::ext_from_url(&href).is_some() {
+            c.push(href);
+        } else {
+            c.page = Some(href);
+        }
+    }
+    let attr = |name: &str| img.attr(name).map(|v| v.to_string().trim().to_string()).filter(|s| !s.is_empty());
+    if let Some(orig) = attr("data-orig-file") {
+        c.push(orig);
+    }
+    for name in ["srcset", "data-srcset"] {
+        if let Some(best) = attr(name).and_then(|s| media::largest_srcset(&s)) {
+            c.push(best);
+        }
+    }
+    for name in ["data-original", "data-src", "data-lazy-src", "src"] {
+        if let Some(url) = attr(name).filter(|u| !u.starts_with("data:")) {
+            c.push(url);
+        }
+    }
+    c
+}
+
+// 视频地址: <video> 中的链接, src 和 <source>, 正文中的 iframe / embed
+// 直接文件和 m3u8 下载, 其他第三方播放器记录为不支持
+fn videos(html: &Elements, page: &str) -> (Vec<String>, Vec<Unsupported>) {
+    let mut videos: Vec<String> = Vec::new();
+    let mut unsupported: Vec<Unsupported> = Vec::new();
+    let base = Url::parse(page).ok();
+    let absolute = |u: &str| match &base {
+        Some(b) => b.join(u).map(|u| u.to_string()).unwrap_or_else(|_| u.to_string()),
+        None => u.to_string(),
+    };
+    // 按补全后的地址去重, 相对地址和绝对地址指向同一文件时只保留一个
+    let mut add = |u: String, embedded: bool| {
+        let u = u.trim();
+        if u.is_empty() {
+            return;
+        }
+        let inline = u.starts_with("blob:") || u.starts_with("data:");
+        let u = if inline { u.to_string() } else { absolute(u) };
+        if videos.contains(&u) || unsupported.iter().any(|x| x.url == u) {
+            return;
+        }
+        if inline {
+            unsupported.push(Unsupported { url: u, reason: "inline media stream".to_string() });
+        } else if !embedded || media::is_hls(&u) || media::ext_from_url(&u).is_some() {
+            videos.push(u);
+        } else {
+            let host = Url::parse(&u).ok()
+                .and_then(|x| x.host_str().map(|h| h.to_string()))
+                .unwrap_or_default();
+            log::warn!("unsupported embedded player {} in {}", &u, page);
+            unsupported.push(Unsupported { reason: format!("embedded player from {} is not supported", host), url: u });
+        }
+    };
+    for (selector, attr) in [("video > a", "href"), ("video[src]", "src"), ("video source[src]", "src")] {
+        attrs(html, selector, attr).into_iter().for_each(|u| add(u, false));
+    }
+    for (selector, attr) in [(".entry-content iframe[src]", "src"), (".entry-content embed[src]", "src")] {
+        attrs(html, selector, attr).into_iter().for_each(|u| add(u, true));
+    }
+    (videos, unsupported)
+}
+
+fn attrs(html: &Elements, selector: &str, attr: &str) -> Vec<String> {
+    let mut v = Vec::new();
+    html.find(selector).into_iter().for_each(|item| {
+        if let Some(value) = item.get_attribute(attr) {
+            v.push(value.to_string());
+        }
+    });
+    v
+}
+
+fn first_attr(html: &Elements, selector: &str, attr: &str) -> Option<String> {
+    attrs(html, selector, attr).into_iter()
+        .map(|s| s.trim().to_string())
+        .find(|s| !s.is_empty())
+}
+
+fn meta_content(html: &Elements, selector: &str) -> Option<String> {
+    first_attr(html, selector, "content")
+}
+
+fn first_text(html: &Elements, selector: &str) -> Option<String> {
+    texts(html, selector).into_iter().next()
+}
+
+// 去空去重的文本列表
+fn texts(html: &Elements, selector: &str) -> Vec<String> {
+    let mut v: Vec<String> = Vec::new();
+    html.find(selector).into_iter().for_each(|item| {
+        let text = item.text().trim().to_string();
+        if !text.is_empty() && !v.contains(&text) {
+            v.push(text);
+        }
+    });
+    v
+}
+
+// 正文文本, text() 会把相邻段落和 <br> 分隔的行连在一起, 先在这些标签后插入换行
+fn content_lines(html: &Elements) -> String {
+    let breaks = Regex::new(r"(?i)<br\s*/?>|</(p|div|li|h[1-6])>").unwrap();
+    let content = html.find(".entry-content").outer_html();
+    Vis::load(breaks.replace_all(&content, "$0\n"))
+        .map(|d| d.find(".entry-content").text())
+        .unwrap_or_default()
+}
+
+// 按 "标签：值" 格式查找正文中的字段
+fn labeled(content: &str, labels: &[&str]) -> Option<String> {
+    for line in content.lines() {
+        let line = line.trim();
+        let (key, value) = match line.split_once('：').or_else(|| line.split_once(':')) {
+            Some(kv) => kv,
+            None => continue
+        };
+        let key = key.trim().to_lowercase();
+        if labels.iter().any(|l| key == *l) {
+            let value = value.trim();
+            if !value.is_empty() {
+                return Some(value.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://a.com/2023/05/post-1.html";
+
+    fn parse_html(html: &str) -> PostDetail {
+        parse(&Vis::load(html).unwrap(), "", URL, "cos")
+    }
+
+    #[test]
+    fn gallery_candidates() {
+        let html = r#"<div class="gallery">
+            <dl class="gallery-item"><dt class="gallery-icon"><a href="https://a.com/up/p1.jpg">
+                <img src="https://a.com/up/p1-300x200.jpg" data-orig-file="https://a.com/up/p1-orig.jpg"
+                     srcset="https://a.com/up/p1-300x200.jpg 300w, https://a.com/up/p1-1024x683.jpg 1024w"></a></dt></dl>
+            <dl class="gallery-item"><dt class="gallery-icon"><a href="https://a.com/post-1/attachment/p2/">
+                <img src="data:image/gif;base64,R0lGOD" data-lazy-src="https://a.com/up/p2-300x200.jpg"></a></dt></dl>
+            <dl class="gallery-item"><dt class="gallery-icon"><img src="https://a.com/up/p3.png"></dt></dl>
+            <dl class="gallery-item"><dt class="gallery-icon"><a href=" "></a></dt></dl>
+        </div>"#;
+        let imgs = parse_html(html).imgs;
+        assert_eq!(imgs.len(), 3);
+        assert_eq!(imgs[0].urls, ["https://a.com/up/p1.jpg", "https://a.com/up/p1-orig.jpg", "https://a.com/up/p1-1024x683.jpg", "https://a.com/up/p1-300x200.jpg"]);
+        assert_eq!(imgs[0].page, None);
+        assert_eq!(imgs[1].urls, ["https://a.com/up/p2-300x200.jpg"]);
+        assert_eq!(imgs[1].page.as_deref(), Some("https://a.com/post-1/attachment/p2/"));
+        assert_eq!(imgs[2].urls, ["https://a.com/up/p3.png"]);
+        assert_eq!(imgs[2].page, None);
+    }
+
+    #[test]
+    fn videos_dedupe_absolute_urls() {
+        let html = r#"<div class="entry-content">
+            <video src="/up/v1.mp4"><source src="https://a.com/up/v1.mp4"><source src="v2.webm" type="video/webm"><a href="/up/v1.mp4">download</a></video>
+            <video><source src="https://cdn.a.com/hls/v3.m3u8"></video>
+            <video src="blob:https://a.com/1234"></video>
+        </div>"#;
+        let detail = parse_html(html);
+        assert_eq!(detail.videos, ["https://a.com/up/v1.mp4", "https://a.com/2023/05/v2.webm", "https://cdn.a.com/hls/v3.m3u8"]);
+        assert_eq!(detail.meta.video_count, 3);
+        assert_eq!(detail.meta.unsupported.len(), 1);
+        assert_eq!(detail.meta.unsupported[0].url, "blob:https://a.com/1234");
+        assert_eq!(detail.meta.unsupported[0].reason, "inline media stream");
+    }
+
+    #[test]
+    fn videos_from_embeds() {
+        let html = r#"<div class="entry-content">
+            <iframe src="//player.example.com/embed/42"></iframe>
+            <iframe src="https://player.example.com/embed/42"></iframe>
+            <iframe src="/up/clip.mp4"></iframe>
+            <embed src="https://a.com/live/index.m3u8">
+        </div>
+        <iframe src="https://ads.example.com/frame"></iframe>"#;
+        let detail = parse_html(html);
+        assert_eq!(detail.videos, ["https://a.com/up/clip.mp4", "https://a.com/live/index.m3u8"]);
+        assert_eq!(detail.meta.unsupported.len(), 1);
+        assert_eq!(detail.meta.unsupported[0].url, "https://player.example.com/embed/42");
+        assert_eq!(detail.meta.unsupported[0].reason, "embedded player from player.example.com is not supported");
+    }
+
+    #[test]
+    fn parse_meta_from_head() {
+        let html = r#"<html><head>
+            <title>Page title</title>
+            <meta property="article:published_time" content="2023-05-01T10:00:00+08:00">
+            <meta name="author" content="alice">
+            <meta name="description" content="A short description">
+            <meta property="og:image" content="https://a.com/cover.jpg">
+        </head><body>
+            <h1 class="entry-title">Post title</h1>
+            <div class="entry-meta"><a rel="category tag" href="/c/cos">Cosplay</a><a rel="category tag" href="/c/cos">Cosplay</a></div>
+            <div class="entry-tags"><a href="/t/1">tag1</a><a href="/t/2">tag2</a></div>
+            <div class="entry-content"><p>COSER：bob</p><p>角色: Saber</p><p>出处：Fate</p></div>
+        </body></html>"#;
+        let meta = parse_html(html).meta;
+        assert_eq!(meta.title, "Post title");
+        assert_eq!((meta.url.as_str(), meta.tag.as_str()), (URL, "cos"));
+        assert_eq!(meta.date.as_deref(), Some("2023-05-01T10:00:00+08:00"));
+        assert_eq!(meta.author.as_deref(), Some("alice"));
+        assert_eq!(meta.categories, ["Cosplay"]);
+        assert_eq!(meta.tags, ["tag1", "tag2"]);
+        assert_eq!(meta.description.as_deref(), Some("A short description"));
+        assert_eq!(meta.cover.as_deref(), Some("https://a.com/cover.jpg"));
+        assert_eq!(meta.coser.as_deref(), Some("bob"));
+        assert_eq!(meta.character.as_deref(), Some("Saber"));
+        assert_eq!(meta.series.as_deref(), Some("Fate"));
+    }
+
+    #[test]
+    fn parse_meta_falls_back_to_body() {
+        let long = "字".repeat(600);
+        let html = format!(r#"<html><head><meta property="og:title" content="OG title"></head><body>
+            <div class="entry-meta"><time datetime="2023-05-02">May 2</time><a rel="author" href="/u/carol">carol</a></div>
+            <div class="entry-content"><p>{}</p><p>cn：<br>备注：无</p></div>
+        </body></html>"#, long);
+        let meta = parse_html(&html).meta;
+        assert_eq!(meta.title, "OG title");
+        assert_eq!(meta.date.as_deref(), Some("2023-05-02"));
+        assert_eq!(meta.author.as_deref(), Some("carol"));
+        assert_eq!(meta.description.as_deref(), Some("字".repeat(DESCRIPTION_LIMIT).as_str()));
+        assert_eq!(meta.coser, None);
+    }
+
+    #[test]
+    fn parse_meta_missing_fields() {
+        let meta = parse_html("<html><body><div class=\"entry-content\"></div></body></html>").meta;
+        assert_eq!(meta.title, naming::post_id(URL));
+        assert_eq!((meta.date, meta.author, meta.description, meta.cover), (None, None, None, None));
+        assert_eq!((meta.coser, meta.character, meta.series), (None, None, None));
+        assert!(meta.categories.is_empty() && meta.tags.is_empty());
+        let json = serde_json::to_value(PostMeta { title: "t".to_string(), ..Default::default() }).unwrap();
+        assert!(json.get("date").is_none() && json.get("tags").is_none());
+    }
+}
@@ -0,0 +1,248 @@
+// 运行结束时的汇总报告, 由下载过程中的事件统计, 打印为表格或写入 JSON 和 Markdown
+use crate::event::{Event, Events};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// 写入输出目录的报告格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Markdown,
+    Both,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(Self::Json),
+            "md" | "markdown" => Some(Self::Markdown),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+}
+
+// 失败的列表页, 帖子, 文件或上传
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure
+{
+    pub kind: String,
+    pub target: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Report
+{
+    pub tool: String,
+    // 开始时间, unix 秒
+    pub started: u64,
+    pub elapsed_ms: u64,
+    pub pages: usize,
+    pub posts_new: usize,
+    // 已下载过或被筛选规则跳过
+    pub posts_skipped: usize,
+    pub posts_failed: usize,
+    pub files: usize,
+    pub files_failed: usize,
+    pub bytes: u64,
+    pub retries: usize,
+    pub archives: usize,
+    pub uploads: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    fn fail(&mut self, kind: &str, target: &str, reason: &str) {
+        self.failures.push(Failure { kind: kind.to_string(), target: target.to_string(), reason: reason.to_string() });
+    }
+
+    fn record(&mut self, e: &Event) {
+        match e {
+            Event::PageFetched { .. } => self.pages += 1,
+            Event::PageFailed { url, error, .. } => self.fail("page", url, error),
+            Event::ListingFailed { listing, error } => self.fail("listing", listing, error),
+            Event::PostSkipped { .. } => self.posts_skipped += 1,
+            Event::PostCompleted { .. } | Event::PostPlanned { .. } => self.posts_new += 1,
+            Event::PostFailed { url, dir, error, .. } => {
+                self.posts_failed += 1;
+                let target = match dir {
+                    Some(dir) if url.is_empty() => dir,
+                    _ => url,
+                };
+                self.fail("post", target, error);
+            }
+            Event::FileRetry { .. } => self.retries += 1,
+            Event::FileCompleted { bytes, .. } => {
+                self.files += 1;
+                self.bytes += bytes;
+            }
+            Event::FileFailed { url, error, .. } => {
+                self.files_failed += 1;
+                self.fail("file", url, error);
+            }
+            Event::ArchiveCreated { .. } => self.archives += 1,
+            Event::UploadDone { ok: true, .. } => self.uploads += 1,
+            Event::UploadDone { file, ok: false } => self.fail("upload", file, "upload failed"),
+            _ => {}
+        }
+    }
+
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("pages scanned", self.pages.to_string()),
+            ("posts new", self.posts_new.to_string()),
+            ("posts skipped", self.posts_skipped.to_string()),
+            ("posts failed", self.posts_failed.to_string()),
+            ("files", self.files.to_string()),
+            ("files failed", self.files_failed.to_string()),
+            ("bytes", self.bytes.to_string()),
+            ("retries", self.retries.to_string()),
+            ("archives", self.archives.to_string()),
+            ("uploads", self.uploads.to_string()),
+            ("elapsed", format!("{:.1}s", self.elapsed_ms as f64 / 1000.0)),
+        ]
+    }
+
+    pub fn print(&self) {
+        println!("{} report", self.tool);
+        for (name, value) in self.rows() {
+            println!("  {:<16} {:>12}", name, value);
+        }
+        if self.failures.is_empty() {
+            return;
+        }
+        println!("failures:");
+        println!("  {:<8} {:<60} reason", "kind", "target");
+        for f in &self.failures {
+            println!("  {:<8} {:<60} {}", f.kind, f.target, f.reason);
+        }
+    }
+
+    pub fn markdown(&self) -> String {
+        let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+        let mut md = format!("# {} report\n\n| item | value |\n|---|---:|\n", self.tool);
+        for (name, value) in self.rows() {
+            md.push_str(&format!("| {} | {} |\n", name, value));
+        }
+        if !self.failures.is_empty() {
+            md.push_str("\n## Failures\n\n| kind | target | reason |\n|---|---|---|\n");
+            for f in &self.failures {
+                md.push_str(&format!("| {} | {} | {} |\n", f.kind, cell(&f.target), cell(&f.reason)));
+            }
+        }
+        md
+    }
+
+    // 写入 dir/<tool>-report-<开始时间>.json 或 .md, 返回写入的文件
+    pub fn write(&self, dir: &Path, format: Format) -> anyhow::Result<Vec<PathBuf>> {
+        let base = dir.join(format!("{}-report-{}", self.tool, self.started));
+        let mut written = Vec::new();
+        if format != Format::Markdown {
+            let path = base.with_extension("json");
+            std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+            written.push(path);
+        }
+        if format != Format::Json {
+            let path = base.with_extension("md");
+            std::fs::write(&path, self.markdown())?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+// 统计经过的事件, 再转发给前端
+#[derive(Clone)]
+pub struct Recorder
+{
+    report: Arc<Mutex<Report>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new(tool: &str) -> Self {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let report = Report { tool: tool.to_string(), started, ..Default::default() };
+        Self { report: Arc::new(Mutex::new(report)), start: Instant::now() }
+    }
+
+    pub fn wrap(&self, next: Events) -> Events {
+        let report = Arc::clone(&self.report);
+        Events::new(move |e| {
+            report.lock().unwrap().record(e);
+            next.emit(e.clone());
+        })
+    }
+
+    // 当前的统计结果, 耗时计到调用时
+    pub fn report(&self) -> Report {
+        let mut report = self.report.lock().unwrap().clone();
+        report.elapsed_ms = crate::event::elapsed_ms(self.start);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(v: &str) -> String {
+        v.to_string()
+    }
+
+    fn run() -> Report {
+        let recorder = Recorder::new("cospull");
+        let forwarded = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&forwarded);
+        let events = recorder.wrap(Events::new(move |_| *count.lock().unwrap() += 1));
+        let listing = s("cos");
+        for e in [
+            Event::PageFetched { listing: listing.clone(), page: 1, url: s("https://a.com/page/1"), posts: 2 },
+            Event::PageFailed { listing: listing.clone(), page: 2, url: s("https://a.com/page/2"), error: s("HTTP 502") },
+            Event::PostSkipped { listing: listing.clone(), url: s("https://a.com/1.html"), rule: s("done"), detail: s("") },
+            Event::FileRetry { url: s("https://a.com/a.jpg"), dir: s("imgs"), attempt: 1, error: s("timeout") },
+            Event::FileCompleted { url: s("https://a.com/a.jpg"), dir: s("imgs"), file: s("imgs/001_a.jpg"), bytes: 1000, duration_ms: 5 },
+            Event::FileCompleted { url: s("https://a.com/b.jpg"), dir: s("imgs"), file: s("imgs/002_b.jpg"), bytes: 24, duration_ms: 5 },
+            Event::FileFailed { url: s("https://a.com/c.jpg"), dir: s("imgs"), error: s("a | b"), duration_ms: 5 },
+            Event::PostCompleted { url: s("https://a.com/2.html"), dir: s("cos/t"), files: 2, bytes: 1024 },
+            Event::PostFailed { listing: listing.clone(), url: s(""), dir: Some(s("cos/x")), error: s("no media") },
+            Event::UploadDone { file: s("0000.tar.gz"), ok: false },
+        ] {
+            events.emit(e);
+        }
+        assert_eq!(*forwarded.lock().unwrap(), 10);
+        let mut report = recorder.report();
+        report.elapsed_ms = 1500;
+        report
+    }
+
+    #[test]
+    fn recorder_counts_events() {
+        let r = run();
+        assert_eq!((r.pages, r.posts_new, r.posts_skipped, r.posts_failed), (1, 1, 1, 1));
+        assert_eq!((r.files, r.files_failed, r.bytes, r.retries, r.uploads), (2, 1, 1024, 1, 0));
+        let failures: Vec<_> = r.failures.iter().map(|f| (f.kind.as_str(), f.target.as_str())).collect();
+        assert_eq!(failures, [
+            ("page", "https://a.com/page/2"),
+            ("file", "https://a.com/c.jpg"),
+            ("post", "cos/x"),
+            ("upload", "0000.tar.gz"),
+        ]);
+    }
+
+    #[test]
+    fn markdown_table() {
+        let md = run().markdown();
+        assert!(md.starts_with("# cospull report\n\n| item | value |\n|---|---:|\n| pages scanned | 1 |\n"));
+        assert!(md.contains("| bytes | 1024 |\n| retries | 1 |\n"));
+        assert!(md.contains("| elapsed | 1.5s |\n"));
+        assert!(md.contains("## Failures\n\n| kind | target | reason |\n|---|---|---|\n| page | https://a.com/page/2 | HTTP 502 |\n"));
+        assert!(md.contains("| file | https://a.com/c.jpg | a \\| b |\n"));
+    }
+}
@@ -0,0 +1,506 @@
+use base64::Engine;
+use image::imageops::FilterType;
+use image::GenericImageView;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::media;
+use crate::check;
+
+// 默认汉明距离阈值, 64 位哈希中不同的位数
+pub const DEFAULT_THRESHOLD: u32 = 8;
+
+// 缩略图最大边长
+const THUMB_SIZE: u32 = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashKind {
+    Average,
+    Difference,
+    Perceptual,
+}
+
+impl HashKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ahash" => Some(Self::Average),
+            "dhash" => Some(Self::Difference),
+            "phash" => Some(Self::Perceptual),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Average => "ahash",
+            Self::Difference => "dhash",
+            Self::Perceptual => "phash",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageHash
+{
+    // 相对输出目录的路径
+    pub path: String,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    pub ahash: String,
+    pub dhash: String,
+    pub phash: String,
+    // 硬链接到同一份数据的其他路径, 去重后的帖子共用 .store 中的数据
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    // 与簇中第一张图片的距离
+    pub distance: u32,
+    // 顺序与 HashKind 一致
+    #[serde(skip)]
+    bits: [u64; 3],
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cluster
+{
+    // 分辨率最高的排在最前, 建议保留
+    pub images: Vec<ImageHash>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report
+{
+    pub root: String,
+    pub hash: String,
+    pub threshold: u32,
+    pub scanned: usize,
+    pub clusters: Vec<Cluster>,
+}
+
+// 扫描输出目录下所有 imgs 目录, 按感知哈希聚类相似图片
+pub fn scan(root: &Path, kind: HashKind, threshold: u32) -> Report {
+    let files = collapse_links(image_files(root));
+    info!("computing perceptual hashes of {} images", files.len());
+    let hashes = hash_all(root, files);
+    let scanned = hashes.len();
+    let clusters = cluster(hashes, kind, threshold);
+    Report {
+        root: root.display().to_string(),
+        hash: kind.name().to_string(),
+        threshold,
+        scanned,
+        clusters,
+    }
+}
+
+fn image_files(root: &Path) -> Vec<PathBuf> {
+    WalkDir::new(root).into_iter()
+        .filter_entry(|e| e.depth() != 1 || !check::is_reserved(e.file_name()))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().parent().and_then(|p| p.file_name()).is_some_and(|n| n == "imgs"))
+        .filter(|e| {
+            let ext = e.path().extension().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+            media::is_image_ext(&ext)
+        })
+        .map(|e| e.into_path())
+        .collect()
+}
+
+// 同一 inode 的文件只计算一次哈希, 否则硬链接的副本会被当作相似图片
+// 返回每份数据的第一个路径和其他链接路径
+fn collapse_links(mut files: Vec<PathBuf>) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    files.sort();
+    let mut seen: BTreeMap<(u64, u64), usize> = BTreeMap::new();
+    let mut out: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    for f in files {
+        let key = match std::fs::metadata(&f) {
+            Ok(m) if m.nlink() > 1 => (m.dev(), m.ino()),
+            _ => {
+                out.push((f, Vec::new()));
+                continue;
+            }
+        };
+        match seen.get(&key) {
+            Some(&i) => out[i].1.push(f),
+            None => {
+                seen.insert(key, out.len());
+                out.push((f, Vec::new()));
+            }
+        }
+    }
+    out
+}
+
+// 多线程解码计算哈希, 无法解码的图片跳过
+fn hash_all(root: &Path, files: Vec<(PathBuf, Vec<PathBuf>)>) -> Vec<ImageHash> {
+    let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk = files.len().div_ceil(workers).max(1);
+    let mut hashes: Vec<ImageHash> = std::thread::scope(|s| {
+        let handles: Vec<_> = files.chunks(chunk)
+            .map(|part| s.spawn(move || part.iter().filter_map(|(f, links)| hash_image(root, f, links)).collect::<Vec<_>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    });
+    hashes.sort_by(|a, b| a.path.cmp(&b.path));
+    hashes
+}
+
+fn hash_image(root: &Path, path: &Path, links: &[PathBuf]) -> Option<ImageHash> {
+    let img = match image::open(path) {
+        Ok(img) => img,
+        Err(e) => {
+            warn!("decode {} error: {}", path.display(), e);
+            return None;
+        }
+    };
+    let (width, height) = img.dimensions();
+    let bits = [average_hash(&img), difference_hash(&img), perceptual_hash(&img)];
+    let relative = |p: &Path| p.strip_prefix(root).unwrap_or(p).display().to_string();
+    Some(ImageHash {
+        path: relative(path),
+        size: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        width,
+        height,
+        ahash: format!("{:016x}", bits[0]),
+        dhash: format!("{:016x}", bits[1]),
+        phash: format!("{:016x}", bits[2]),
+        links: links.iter().map(|l| relative(l)).collect(),
+        distance: 0,
+        bits,
+    })
+}
+
+fn gray(img: &image::DynamicImage, w: u32, h: u32) -> Vec<f64> {
+    img.resize_exact(w, h, FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|p| p.0[0] as f64)
+        .collect()
+}
+
+fn to_bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0u64, |acc, b| (acc << 1) | b as u64)
+}
+
+// 8x8 灰度图, 与均值比较
+fn average_hash(img: &image::DynamicImage) -> u64 {
+    let px = gray(img, 8, 8);
+    let mean = px.iter().sum::<f64>() / px.len() as f64;
+    to_bits(px.iter().map(|&p| p > mean))
+}
+
+// 9x8 灰度图, 相邻像素比较
+fn difference_hash(img: &image::DynamicImage) -> u64 {
+    let px = gray(img, 9, 8);
+    to_bits((0..8).flat_map(|y| (0..8).map(move |x| (y, x))).map(|(y, x)| px[y * 9 + x] < px[y * 9 + x + 1]))
+}
+
+// 32x32 灰度图做 DCT, 低频 8x8 系数与中位数比较
+fn perceptual_hash(img: &image::DynamicImage) -> u64 {
+    const N: usize = 32;
+    let px = gray(img, N as u32, N as u32);
+    let cos: Vec<Vec<f64>> = (0..8)
+        .map(|u| (0..N).map(|x| ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * N) as f64).cos()).collect())
+        .collect();
+    let mut coef = [0f64; 64];
+    for u in 0..8 {
+        for v in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += px[y * N + x] * cos[u][y] * cos[v][x];
+                }
+            }
+            coef[u * 8 + v] = sum;
+        }
+    }
+    // 直流分量不参与中位数
+    let mut sorted: Vec<f64> = coef[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[sorted.len() / 2];
+    to_bits(coef.iter().map(|&c| c > median))
+}
+
+// 按汉明距离组织的 BK 树, 查询时只访问距离可能在阈值内的子树
+struct BkTree
+{
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode
+{
+    hash: u64,
+    // 图片序号
+    index: usize,
+    // (与本节点的距离, 节点序号)
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new = self.nodes.len();
+        self.nodes.push(BkNode { hash, index, children: Vec::new() });
+        if new == 0 {
+            return;
+        }
+        let mut cur = 0;
+        loop {
+            let d = (self.nodes[cur].hash ^ hash).count_ones();
+            match self.nodes[cur].children.iter().find(|(cd, _)| *cd == d) {
+                Some(&(_, child)) => cur = child,
+                None => {
+                    self.nodes[cur].children.push((d, new));
+                    return;
+                }
+            }
+        }
+    }
+
+    // 距离不超过 threshold 的图片序号
+    fn query(&self, hash: u64, threshold: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(cur) = stack.pop() {
+            let node = &self.nodes[cur];
+            let d = (node.hash ^ hash).count_ones();
+            if d <= threshold {
+                found.push(node.index);
+            }
+            stack.extend(node.children.iter().filter(|(cd, _)| cd.abs_diff(d) <= threshold).map(|(_, c)| *c));
+        }
+        found
+    }
+}
+
+// 汉明距离在阈值内的图片并查集聚类, 只保留多于一张的簇
+// 先插入 BK 树再查询每张图片的近邻, 避免两两比较
+fn cluster(hashes: Vec<ImageHash>, kind: HashKind, threshold: u32) -> Vec<Cluster> {
+    let k = kind as usize;
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut r = i;
+        while parent[r] != r {
+            r = parent[r];
+        }
+        parent[i] = r;
+        r
+    }
+    let mut tree = BkTree::new();
+    for (i, h) in hashes.iter().enumerate() {
+        tree.insert(h.bits[k], i);
+    }
+    for (i, h) in hashes.iter().enumerate() {
+        for j in tree.query(h.bits[k], threshold) {
+            let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+            if a != b {
+                parent[b.max(a)] = a.min(b);
+            }
+        }
+    }
+    let mut groups: BTreeMap<usize, Vec<ImageHash>> = BTreeMap::new();
+    for (i, h) in hashes.into_iter().enumerate() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(h);
+    }
+    groups.into_values()
+        .filter(|g| g.len() > 1)
+        .map(|mut images| {
+            images.sort_by_key(|img| std::cmp::Reverse((img.width as u64 * img.height as u64, img.size)));
+            let first = images[0].bits[k];
+            for img in images.iter_mut() {
+                img.distance = (img.bits[k] ^ first).count_ones();
+            }
+            Cluster { images }
+        })
+        .collect()
+}
+
+// JPEG 缩略图, 嵌入 HTML
+fn thumbnail(path: &Path) -> Option<String> {
+    let img = image::open(path).ok()?.thumbnail(THUMB_SIZE, THUMB_SIZE);
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.to_rgb8().write_to(&mut buf, image::ImageOutputFormat::Jpeg(75)).ok()?;
+    Some(format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(buf.into_inner())))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// 在文件名后追加扩展名, 前缀中的点不当作扩展名, 如 report.v2
+fn with_suffix(prefix: &Path, ext: &str) -> PathBuf {
+    let mut name = prefix.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+// 写入 <prefix>.json 和 <prefix>.html
+pub fn write_report(report: &Report, prefix: &Path) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let json = with_suffix(prefix, "json");
+    std::fs::write(&json, serde_json::to_vec_pretty(report)?)?;
+
+    let root = PathBuf::from(&report.root);
+    let mut html = String::new();
+    html += "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>cospull similar</title>\n";
+    html += "<style>body{font-family:sans-serif}.cluster{border:1px solid #ccc;margin:8px;padding:8px}";
+    html += ".img{display:inline-block;margin:4px;vertical-align:top;max-width:180px;font-size:12px;word-break:break-all}";
+    html += ".keep{outline:3px solid #4a4}</style></head><body>\n";
+    html += &format!("<h1>{} clusters in {} images</h1>\n<p>{} threshold {} in {}</p>\n",
+        report.clusters.len(), report.scanned, report.hash, report.threshold, escape(&report.root));
+    for (n, c) in report.clusters.iter().enumerate() {
+        html += &format!("<div class=\"cluster\"><h3>#{} ({} images)</h3>\n", n + 1, c.images.len());
+        for (i, img) in c.images.iter().enumerate() {
+            let thumb = thumbnail(&root.join(&img.path));
+            let links: String = img.links.iter().map(|l| format!("<br>= {}", escape(l))).collect();
+            html += &format!("<div class=\"img{}\"><img src=\"{}\" alt=\"\"><br>{}{}<br>{}x{} {} bytes, distance {}</div>\n",
+                if i == 0 { " keep" } else { "" },
+                thumb.as_deref().unwrap_or(""),
+                escape(&img.path), links, img.width, img.height, img.size, img.distance);
+        }
+        html += "</div>\n";
+    }
+    html += "</body></html>\n";
+    let html_path = with_suffix(prefix, "html");
+    std::fs::write(&html_path, html)?;
+    Ok((json, html_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 左暗右亮的水平渐变
+    fn gradient(w: u32, h: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(w, h, |x, _| image::Luma([(x * 255 / (w - 1)) as u8])))
+    }
+
+    // 左上到右下的斜向图案
+    fn pattern(w: u32, h: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(w, h, |x, y| {
+            image::Luma([if (x * 8 / w + y * 8 / h).is_multiple_of(3) { 230 } else { 20 }])
+        }))
+    }
+
+    fn distance(a: u64, b: u64) -> u32 {
+        (a ^ b).count_ones()
+    }
+
+    fn hash(path: &str, bits: u64, width: u32) -> ImageHash {
+        ImageHash {
+            path: path.to_string(),
+            size: 0,
+            width,
+            height: width,
+            ahash: String::new(),
+            dhash: String::new(),
+            phash: String::new(),
+            links: Vec::new(),
+            distance: 0,
+            bits: [0, 0, bits],
+        }
+    }
+
+    #[test]
+    fn average_and_difference_hash() {
+        let img = gradient(64, 48);
+        assert_eq!(average_hash(&img), 0x0f0f_0f0f_0f0f_0f0f);
+        assert_eq!(difference_hash(&img), u64::MAX);
+        let mut flipped = img.clone();
+        flipped.invert();
+        assert_eq!(average_hash(&flipped), 0xf0f0_f0f0_f0f0_f0f0);
+        assert_eq!(difference_hash(&flipped), 0);
+    }
+
+    #[test]
+    fn perceptual_hash_survives_resize() {
+        let img = pattern(256, 256);
+        let small = img.resize_exact(100, 100, FilterType::Triangle);
+        let mut inverted = img.clone();
+        inverted.invert();
+        let h = perceptual_hash(&img);
+        assert!(distance(h, perceptual_hash(&small)) <= DEFAULT_THRESHOLD);
+        assert!(distance(h, perceptual_hash(&inverted)) > 32);
+        assert!(distance(h, perceptual_hash(&gradient(256, 256))) > DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn bk_tree_matches_pairwise_search() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut hashes: Vec<u64> = (0..300).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        }).collect();
+        // 加入一些近似和相同的哈希
+        let near: Vec<u64> = (0..50).map(|i| hashes[i] ^ (1u64 << (i % 64)) ^ (1u64 << ((i * 7) % 64))).collect();
+        hashes.extend(near);
+        hashes.push(hashes[0]);
+        let mut tree = BkTree::new();
+        for (i, h) in hashes.iter().enumerate() {
+            tree.insert(*h, i);
+        }
+        for threshold in [0, 2, 8, 20] {
+            for h in &hashes {
+                let mut found = tree.query(*h, threshold);
+                found.sort();
+                let expected: Vec<usize> = (0..hashes.len()).filter(|&j| distance(*h, hashes[j]) <= threshold).collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn cluster_joins_transitively_and_drops_singletons() {
+        let hashes = vec![
+            hash("a.jpg", 0, 100),
+            hash("b.jpg", 0b1111, 200),
+            hash("c.jpg", 0b1111_1111, 50),
+            hash("d.jpg", u64::MAX, 100),
+            hash("e.jpg", u64::MAX ^ 1, 300),
+        ];
+        let clusters = cluster(hashes, HashKind::Perceptual, 4);
+        let names: Vec<Vec<(&str, u32)>> = clusters.iter()
+            .map(|c| c.images.iter().map(|i| (i.path.as_str(), i.distance)).collect())
+            .collect();
+        assert_eq!(names, [vec![("b.jpg", 0), ("a.jpg", 4), ("c.jpg", 4)], vec![("e.jpg", 0), ("d.jpg", 1)]]);
+        assert!(cluster(vec![hash("a.jpg", 0, 1), hash("b.jpg", 0b11111, 1)], HashKind::Perceptual, 4).is_empty());
+    }
+
+    #[test]
+    fn scan_collapses_hard_links_and_writes_report() {
+        let root = std::env::temp_dir().join(format!("cosjun-similar-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (p1, p2) = (root.join("tag").join("p1").join("imgs"), root.join("tag").join("p2").join("imgs"));
+        std::fs::create_dir_all(&p1).unwrap();
+        std::fs::create_dir_all(&p2).unwrap();
+        pattern(256, 256).save(p1.join("001_a.png")).unwrap();
+        std::fs::hard_link(p1.join("001_a.png"), p2.join("001_a.png")).unwrap();
+        pattern(256, 256).resize_exact(120, 120, FilterType::Triangle).save(p2.join("002_b.png")).unwrap();
+        gradient(64, 64).save(p2.join("003_c.png")).unwrap();
+
+        let report = scan(&root, HashKind::Perceptual, DEFAULT_THRESHOLD);
+        assert_eq!(report.scanned, 3);
+        assert_eq!(report.clusters.len(), 1);
+        let images = &report.clusters[0].images;
+        assert_eq!(images[0].path, "tag/p1/imgs/001_a.png");
+        assert_eq!(images[0].links, ["tag/p2/imgs/001_a.png"]);
+        assert_eq!(images[1].path, "tag/p2/imgs/002_b.png");
+
+        let (json, html) = write_report(&report, &root.join("report.v2")).unwrap();
+        assert_eq!(json, root.join("report.v2.json"));
+        assert_eq!(html, root.join("report.v2.html"));
+        assert!(json.is_file() && html.is_file());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,267 @@
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::check;
+use crate::media::META_FILES;
+
+// 输出目录下的内容寻址存储
+pub const STORE_DIR: &str = ".store";
+
+// reflink 数据的引用记录扩展名
+const REFS_EXT: &str = "refs";
+
+// 按 sha256 保存文件, 帖子目录中的文件硬链接到同一份数据
+// 不支持硬链接时使用 reflink, 引用的文件记录在数据旁的 .refs 中
+#[derive(Debug, Clone)]
+pub struct Store
+{
+    root: PathBuf,
+}
+
+// 放入存储的结果
+#[derive(Debug, PartialEq)]
+pub enum Placed {
+    // 第一次出现, 加入存储
+    New,
+    // 已存在相同内容, 链接到已有数据, 节省的字节数
+    Linked(u64),
+    // 已经是存储中的数据
+    Same,
+}
+
+#[derive(Debug, Default)]
+pub struct DedupeStats
+{
+    pub files: usize,
+    pub linked: usize,
+    pub saved: u64,
+    pub pruned: usize,
+    pub freed: u64,
+}
+
+impl Store {
+    pub fn new(output: &Path) -> Self {
+        Self { root: output.join(STORE_DIR) }
+    }
+
+    // cosdup 的下载目录可以是 cospull 的输出目录或其中的标签目录, 查找已有的存储
+    pub fn locate(dir: &Path) -> Option<Self> {
+        [Some(dir), dir.parent()].into_iter().flatten()
+            .find(|d| d.join(STORE_DIR).is_dir())
+            .map(Store::new)
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+
+    fn output(&self) -> &Path {
+        self.root.parent().unwrap_or(&self.root)
+    }
+
+    // reflink 的数据链接数始终为 1, 记录引用它的文件, 路径相对输出目录
+    fn add_ref(&self, blob: &Path, file: &Path) -> std::io::Result<()> {
+        let refs = blob.with_extension(REFS_EXT);
+        let file = file.strip_prefix(self.output()).map(|p| p.to_path_buf())
+            .or_else(|_| std::fs::canonicalize(file))?;
+        let line = file.to_string_lossy().to_string();
+        let existing = std::fs::read_to_string(&refs).unwrap_or_default();
+        if existing.lines().any(|l| l == line) {
+            return Ok(());
+        }
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(&refs)?;
+        writeln!(f, "{}", line)
+    }
+
+    // 数据是否仍被帖子引用, 硬链接看链接数, reflink 看 .refs 中的文件是否还在
+    fn referenced(&self, blob: &Path, meta: &std::fs::Metadata) -> bool {
+        if meta.nlink() > 1 {
+            return true;
+        }
+        match std::fs::read_to_string(blob.with_extension(REFS_EXT)) {
+            Ok(refs) => refs.lines().any(|l| self.output().join(l).is_file()),
+            Err(_) => false,
+        }
+    }
+
+    // 计算文件哈希并放入存储, 已有相同内容时替换为链接
+    pub fn place(&self, file: &Path) -> anyhow::Result<Placed> {
+        let hash = hash_file(file)?;
+        let blob = self.blob_path(&hash);
+        if !blob.exists() {
+            if let Some(p) = blob.parent() {
+                std::fs::create_dir_all(p)?;
+            }
+            if link(file, &blob)? == Link::Reflink {
+                self.add_ref(&blob, file)?;
+            }
+            return Ok(Placed::New);
+        }
+        let (fm, bm) = (std::fs::metadata(file)?, std::fs::metadata(&blob)?);
+        if fm.dev() == bm.dev() && fm.ino() == bm.ino() {
+            return Ok(Placed::Same);
+        }
+        // 先链接到临时文件再替换, 失败时保留原文件
+        let tmp = file.with_file_name(format!(".{}.link", hash));
+        let how = link(&blob, &tmp)?;
+        if let Err(e) = std::fs::rename(&tmp, file) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        if how == Link::Reflink {
+            self.add_ref(&blob, file)?;
+        }
+        Ok(Placed::Linked(fm.len()))
+    }
+
+    // 删除没有被任何帖子引用的数据, 帖子目录被 cosdup 清理后回收空间
+    pub fn prune(&self) -> (usize, u64) {
+        let (mut count, mut bytes) = (0, 0);
+        for entry in WalkDir::new(&self.root).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() || entry.path().extension().is_some_and(|e| e == REFS_EXT) {
+                continue;
+            }
+            let meta = match entry.metadata() {
+                Ok(m) => m,
+                Err(_) => continue
+            };
+            if self.referenced(entry.path(), &meta) {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(_) => {
+                    let _ = std::fs::remove_file(entry.path().with_extension(REFS_EXT));
+                    count += 1;
+                    bytes += meta.len();
+                }
+                Err(e) => warn!("remove blob {} error: {}", entry.path().display(), e),
+            }
+        }
+        if count > 0 {
+            info!("pruned {} unreferenced blobs, {} bytes", count, bytes);
+        }
+        (count, bytes)
+    }
+
+    // 合并输出目录中已有的重复文件
+    pub fn dedupe(&self, output: &Path) -> DedupeStats {
+        let mut stats = DedupeStats::default();
+        let walker = WalkDir::new(output).into_iter()
+            .filter_entry(|e| e.depth() != 1 || !check::is_reserved(e.file_name()));
+        for entry in walker.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy();
+            if !entry.file_type().is_file() || name.starts_with('.') || META_FILES.contains(&name.as_ref()) {
+                continue;
+            }
+            stats.files += 1;
+            match self.place(entry.path()) {
+                Ok(Placed::Linked(size)) => {
+                    info!("{} is a duplicate, linked", entry.path().display());
+                    stats.linked += 1;
+                    stats.saved += size;
+                }
+                Ok(_) => {}
+                Err(e) => warn!("dedupe {} error: {}", entry.path().display(), e),
+            }
+        }
+        let (pruned, freed) = self.prune();
+        stats.pruned = pruned;
+        stats.freed = freed;
+        stats
+    }
+}
+
+// 计算文件 sha256, 去重和压缩包校验共用
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    hash_reader(&mut std::fs::File::open(path)?)
+}
+
+// 计算数据流 sha256, 如压缩包内的文件
+pub fn hash_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[derive(Debug, PartialEq)]
+enum Link {
+    Hard,
+    Reflink,
+}
+
+// 优先硬链接, 不支持时尝试 reflink
+fn link(src: &Path, dst: &Path) -> anyhow::Result<Link> {
+    let err = match std::fs::hard_link(src, dst) {
+        Ok(_) => return Ok(Link::Hard),
+        Err(e) => e,
+    };
+    let status = std::process::Command::new("cp")
+        .arg("--reflink=always")
+        .arg(src)
+        .arg(dst)
+        .stderr(std::process::Stdio::null())
+        .status();
+    match status {
+        Ok(s) if s.success() => Ok(Link::Reflink),
+        _ => {
+            let _ = std::fs::remove_file(dst);
+            anyhow::bail!("link {} to {} error: {}", src.display(), dst.display(), err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cosjun-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn prune_keeps_hard_linked_blobs_until_posts_are_removed() {
+        let out = temp_dir("hard");
+        let store = Store::new(&out);
+        let (a, b) = (out.join("a.jpg"), out.join("b.jpg"));
+        std::fs::write(&a, b"same").unwrap();
+        std::fs::write(&b, b"same").unwrap();
+        assert_eq!(store.place(&a).unwrap(), Placed::New);
+        assert_eq!(store.place(&b).unwrap(), Placed::Linked(4));
+        assert_eq!(store.prune().0, 0);
+        std::fs::remove_file(&a).unwrap();
+        std::fs::remove_file(&b).unwrap();
+        assert_eq!(store.prune(), (1, 4));
+        let _ = std::fs::remove_dir_all(&out);
+    }
+
+    #[test]
+    fn prune_keeps_reflinked_blobs_while_refs_exist() {
+        let out = temp_dir("reflink");
+        let store = Store::new(&out);
+        let hash = hash_file_bytes(b"data");
+        let blob = store.blob_path(&hash);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(&blob, b"data").unwrap();
+        let post = out.join("post.jpg");
+        std::fs::write(&post, b"data").unwrap();
+        store.add_ref(&blob, &post).unwrap();
+        store.add_ref(&blob, &post).unwrap();
+        assert_eq!(std::fs::read_to_string(blob.with_extension(REFS_EXT)).unwrap(), "post.jpg\n");
+        assert_eq!(store.prune().0, 0);
+        std::fs::remove_file(&post).unwrap();
+        assert_eq!(store.prune(), (1, 4));
+        assert!(!blob.with_extension(REFS_EXT).exists());
+        let _ = std::fs::remove_dir_all(&out);
+    }
+
+    fn hash_file_bytes(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+}